@@ -0,0 +1,451 @@
+//! Input capture
+//!
+//! Latches the value of a timer counter when an edge is detected on one of the timer channels.
+//! This can be used to timestamp events, and to measure pulse widths or frequencies.
+
+use core::marker::PhantomData;
+
+use cast::{u16, u32};
+use nb;
+use stm32f30x::{TIM15, TIM16, TIM17, TIM2, TIM3, TIM4};
+
+use rcc::{APB1, APB2, Clocks};
+use time::Hertz;
+use timer::{CPin, Channel, TriggerChannel};
+
+/// Input capture error
+#[derive(Debug)]
+pub enum Error {
+    /// A new value was captured before the previous one was read
+    Overcapture,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Signal edge that triggers a capture
+pub enum Edge {
+    /// Rising edge
+    Rising,
+    /// Falling edge
+    Falling,
+    /// Both rising and falling edges
+    Both,
+}
+
+/// Input capture prescaler
+pub enum Prescaler {
+    /// Capture on every edge
+    Div1 = 0b00,
+    /// Capture once every 2 edges
+    Div2 = 0b01,
+    /// Capture once every 4 edges
+    Div4 = 0b10,
+    /// Capture once every 8 edges
+    Div8 = 0b11,
+}
+
+/// Input filter
+///
+/// A transition is only validated after `N` consecutive samples, taken at the indicated sampling
+/// frequency, agree on the new level. `fCK_INT` is the timer clock; `fDTS` is equal to `fCK_INT`.
+pub enum Filter {
+    /// No filter, sampling is done at `fDTS`
+    NoFilter = 0b0000,
+    /// `fSAMPLING = fCK_INT`, `N = 2`
+    CkIntN2 = 0b0001,
+    /// `fSAMPLING = fCK_INT`, `N = 4`
+    CkIntN4 = 0b0010,
+    /// `fSAMPLING = fCK_INT`, `N = 8`
+    CkIntN8 = 0b0011,
+    /// `fSAMPLING = fDTS / 2`, `N = 6`
+    Dts2N6 = 0b0100,
+    /// `fSAMPLING = fDTS / 2`, `N = 8`
+    Dts2N8 = 0b0101,
+    /// `fSAMPLING = fDTS / 4`, `N = 6`
+    Dts4N6 = 0b0110,
+    /// `fSAMPLING = fDTS / 4`, `N = 8`
+    Dts4N8 = 0b0111,
+    /// `fSAMPLING = fDTS / 8`, `N = 6`
+    Dts8N6 = 0b1000,
+    /// `fSAMPLING = fDTS / 8`, `N = 8`
+    Dts8N8 = 0b1001,
+    /// `fSAMPLING = fDTS / 16`, `N = 5`
+    Dts16N5 = 0b1010,
+    /// `fSAMPLING = fDTS / 16`, `N = 6`
+    Dts16N6 = 0b1011,
+    /// `fSAMPLING = fDTS / 16`, `N = 8`
+    Dts16N8 = 0b1100,
+    /// `fSAMPLING = fDTS / 32`, `N = 5`
+    Dts32N5 = 0b1101,
+    /// `fSAMPLING = fDTS / 32`, `N = 6`
+    Dts32N6 = 0b1110,
+    /// `fSAMPLING = fDTS / 32`, `N = 8`
+    Dts32N8 = 0b1111,
+}
+
+/// Input capture on a single timer channel
+pub struct Capture<TIM, CHANNEL, PIN> {
+    tim: TIM,
+    pin: PIN,
+    frequency: Hertz,
+    _channel: PhantomData<CHANNEL>,
+}
+
+/// PWM input mode: measures the period and the duty cycle of a signal
+///
+/// The signal is captured on channel 1 or 2; the other channel of the pair is used internally
+/// to capture the falling edges.
+pub struct PwmInput<TIM, CHANNEL, PIN> {
+    tim: TIM,
+    pin: PIN,
+    frequency: Hertz,
+    _channel: PhantomData<CHANNEL>,
+}
+
+/// A PWM input measurement
+pub struct Pwm {
+    /// Period of the signal, in timer ticks
+    pub period: u32,
+    /// Time the signal stays high during a period, in timer ticks
+    pub duty: u32,
+    /// Frequency of the signal
+    pub frequency: Hertz,
+}
+
+macro_rules! hal {
+    ($($TIM:ident: (
+        $tim:ident,
+        $APB:ident,
+        $timXen:ident,
+        $timXrst:ident,
+        $pclkX:ident,
+        $ppreX:ident,
+        $width:ident,
+        [$(($i:pat, $ccmrX:ident, $ccrX:ident),)+]
+    ),)+) => {
+        $(
+            impl<CHANNEL, PIN> Capture<$TIM, CHANNEL, PIN>
+            where
+                CHANNEL: Channel,
+            {
+                /// Configures a TIM channel to capture the counter value on every `edge`
+                ///
+                /// The counter ticks at `resolution` or at the closest frequency the prescaler can
+                /// achieve; see `frequency`. The counter is free running: it wraps around once it
+                /// reaches the maximum value of its width (16 bits, or 32 bits for TIM2).
+                // NOTE(allow) `w.psc().bits()` is safe for TIM{15,16,17} but not for TIM{2,3,4}
+                // due to some SVD omission
+                #[allow(unused_unsafe)]
+                pub fn $tim<F>(
+                    tim: $TIM,
+                    pin: PIN,
+                    edge: Edge,
+                    resolution: F,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    F: Into<Hertz>,
+                    PIN: CPin<$TIM, CHANNEL>,
+                {
+                    // enable and reset peripheral to a clean slate state
+                    apb.enr().modify(|_, w| w.$timXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().clear_bit());
+
+                    let timclk =
+                        clocks.$pclkX().0 * if clocks.$ppreX() == 1 { 1 } else { 2 };
+                    let ticks = timclk / resolution.into().0;
+                    assert!(ticks > 0, "resolution higher than the timer clock");
+
+                    let psc = u16(ticks - 1).unwrap();
+                    tim.psc.write(|w| unsafe { w.psc().bits(psc) });
+                    tim.arr.write(|w| unsafe { w.bits(u32($width::max_value())) });
+
+                    // UG: load the new prescaler value
+                    tim.egr.write(|w| w.ug().set_bit());
+
+                    let mut capture = Capture {
+                        tim,
+                        pin,
+                        frequency: Hertz(timclk / (u32(psc) + 1)),
+                        _channel: PhantomData,
+                    };
+
+                    // CCxS: ICx is mapped on TIx; no prescaler, no filter
+                    capture.modify_ccmr(0b1111_1111, 0b01);
+                    capture.set_edge(edge);
+
+                    // CCxE: enable the capture
+                    let offset = 4 * u32(CHANNEL::INDEX);
+                    capture
+                        .tim
+                        .ccer
+                        .modify(|r, w| unsafe { w.bits(r.bits() | (1 << offset)) });
+
+                    // start counter
+                    capture.tim.cr1.modify(|_, w| w.cen().set_bit());
+
+                    capture
+                }
+
+                /// Returns the counter value latched by the most recent capture
+                ///
+                /// Returns `WouldBlock` if nothing has been captured since the last call. An
+                /// `Overcapture` error indicates that at least one capture was lost; the next call
+                /// returns the most recent capture.
+                pub fn capture(&mut self) -> nb::Result<u32, Error> {
+                    let sr = self.tim.sr.read().bits();
+                    let ccif = 1 << (1 + CHANNEL::INDEX);
+                    let ccof = 1 << (9 + CHANNEL::INDEX);
+
+                    Err(if sr & ccof != 0 {
+                        // NOTE(unsafe) the SR flags are cleared by writing zero; ones are ignored
+                        self.tim.sr.write(|w| unsafe { w.bits(!ccof) });
+                        nb::Error::Other(Error::Overcapture)
+                    } else if sr & ccif != 0 {
+                        // NOTE reading CCRx clears the CCxIF flag
+                        return Ok(self.ccr());
+                    } else {
+                        nb::Error::WouldBlock
+                    })
+                }
+
+                /// Returns the frequency at which the counter ticks
+                pub fn frequency(&self) -> Hertz {
+                    self.frequency
+                }
+
+                /// Changes the edge that triggers a capture
+                pub fn set_edge(&mut self, edge: Edge) {
+                    // CCxNP:CCxP
+                    let bits = match edge {
+                        Edge::Rising => 0b0000,
+                        Edge::Falling => 0b0010,
+                        Edge::Both => 0b1010,
+                    };
+
+                    let offset = 4 * u32(CHANNEL::INDEX);
+                    self.tim.ccer.modify(|r, w| unsafe {
+                        w.bits((r.bits() & !(0b1010 << offset)) | (bits << offset))
+                    });
+                }
+
+                /// Changes the number of edges that must be detected to trigger a capture
+                pub fn set_prescaler(&mut self, prescaler: Prescaler) {
+                    // ICxPSC
+                    self.modify_ccmr(0b0000_1100, (prescaler as u32) << 2);
+                }
+
+                /// Changes the digital filter applied to the input signal
+                pub fn set_filter(&mut self, filter: Filter) {
+                    // ICxF
+                    self.modify_ccmr(0b1111_0000, (filter as u32) << 4);
+                }
+
+                /// Starts listening for capture events
+                pub fn listen(&mut self) {
+                    // CCxIE
+                    let bit = 1 + CHANNEL::INDEX;
+                    self.tim.dier.modify(|r, w| unsafe { w.bits(r.bits() | (1 << bit)) });
+                }
+
+                /// Stops listening for capture events
+                pub fn unlisten(&mut self) {
+                    // CCxIE
+                    let bit = 1 + CHANNEL::INDEX;
+                    self.tim.dier.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << bit)) });
+                }
+
+                /// Releases the TIM peripheral and the channel pin
+                pub fn free(self) -> ($TIM, PIN) {
+                    // pause counter
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    (self.tim, self.pin)
+                }
+
+                fn modify_ccmr(&mut self, mask: u32, bits: u32) {
+                    let offset = 8 * u32(CHANNEL::INDEX % 2);
+
+                    match CHANNEL::INDEX {
+                        $(
+                            $i => self.tim.$ccmrX.modify(|r, w| unsafe {
+                                w.bits((r.bits() & !(mask << offset)) | (bits << offset))
+                            }),
+                        )+
+                        _ => unreachable!(),
+                    }
+                }
+
+                fn ccr(&self) -> u32 {
+                    match CHANNEL::INDEX {
+                        $(
+                            $i => self.tim.$ccrX.read().bits(),
+                        )+
+                        _ => unreachable!(),
+                    }
+                }
+            }
+        )+
+    }
+}
+
+macro_rules! pwm {
+    ($($TIM:ident: ($tim:ident, $APB:ident, $timXen:ident, $timXrst:ident, $pclkX:ident, $ppreX:ident, $width:ident),)+) => {
+        $(
+            impl<CHANNEL, PIN> PwmInput<$TIM, CHANNEL, PIN>
+            where
+                CHANNEL: TriggerChannel,
+            {
+                /// Configures a TIM peripheral in PWM input mode
+                ///
+                /// The counter ticks at `resolution` or at the closest frequency the prescaler can
+                /// achieve. The counter is reset on every rising edge of the signal, so the period
+                /// of the signal must be shorter than the wrap around time of the counter.
+                // NOTE(allow) `w.psc().bits()` is safe for TIM{15,16,17} but not for TIM{2,3,4}
+                // due to some SVD omission
+                #[allow(unused_unsafe)]
+                pub fn $tim<F>(
+                    tim: $TIM,
+                    pin: PIN,
+                    resolution: F,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    F: Into<Hertz>,
+                    PIN: CPin<$TIM, CHANNEL>,
+                {
+                    // enable and reset peripheral to a clean slate state
+                    apb.enr().modify(|_, w| w.$timXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().clear_bit());
+
+                    let timclk =
+                        clocks.$pclkX().0 * if clocks.$ppreX() == 1 { 1 } else { 2 };
+                    let ticks = timclk / resolution.into().0;
+                    assert!(ticks > 0, "resolution higher than the timer clock");
+
+                    let psc = u16(ticks - 1).unwrap();
+                    tim.psc.write(|w| unsafe { w.psc().bits(psc) });
+                    tim.arr.write(|w| unsafe { w.bits(u32($width::max_value())) });
+
+                    // UG: load the new prescaler value
+                    tim.egr.write(|w| w.ug().set_bit());
+
+                    // `i`: channel connected to the signal; `j`: its companion channel
+                    let i = u32(CHANNEL::INDEX);
+                    let j = 1 - i;
+
+                    // CCiS: ICi is mapped on TIi
+                    // CCjS: ICj is mapped on TIi
+                    tim.ccmr1_output
+                        .write(|w| unsafe { w.bits((0b01 << (8 * i)) | (0b10 << (8 * j))) });
+
+                    // CCiE, CCiP: capture rising edges
+                    // CCjE, CCjP: capture falling edges
+                    tim.ccer
+                        .write(|w| unsafe { w.bits((0b01 << (4 * i)) | (0b11 << (4 * j))) });
+
+                    // TS: TI1FP1 or TI2FP2 is the trigger input
+                    // SMS: reset mode, the trigger input reinitializes the counter
+                    let ts = if i == 0 { 0b101 } else { 0b110 };
+                    tim.smcr.write(|w| unsafe { w.ts().bits(ts).sms().bits(0b100) });
+
+                    // start counter
+                    tim.cr1.modify(|_, w| w.cen().set_bit());
+
+                    PwmInput {
+                        tim,
+                        pin,
+                        frequency: Hertz(timclk / (u32(psc) + 1)),
+                        _channel: PhantomData,
+                    }
+                }
+
+                /// Returns the measurement of the most recently completed period
+                ///
+                /// Returns `WouldBlock` if no period has been completed since the last call.
+                pub fn read(&mut self) -> nb::Result<Pwm, Error> {
+                    let sr = self.tim.sr.read().bits();
+                    let ccif = 1 << (1 + CHANNEL::INDEX);
+                    let ccof = 1 << (9 + CHANNEL::INDEX);
+
+                    Err(if sr & ccof != 0 {
+                        // NOTE(unsafe) the SR flags are cleared by writing zero; ones are ignored
+                        self.tim.sr.write(|w| unsafe { w.bits(!ccof) });
+                        nb::Error::Other(Error::Overcapture)
+                    } else if sr & ccif != 0 {
+                        // NOTE reading CCRx clears the CCxIF flag
+                        let ccr1 = self.tim.ccr1.read().bits();
+                        let ccr2 = self.tim.ccr2.read().bits();
+
+                        let (period, duty) = if CHANNEL::INDEX == 0 {
+                            (ccr1, ccr2)
+                        } else {
+                            (ccr2, ccr1)
+                        };
+
+                        return Ok(Pwm {
+                            period,
+                            duty,
+                            frequency: Hertz(self.frequency.0.checked_div(period).unwrap_or(0)),
+                        });
+                    } else {
+                        nb::Error::WouldBlock
+                    })
+                }
+
+                /// Returns the frequency at which the counter ticks
+                pub fn frequency(&self) -> Hertz {
+                    self.frequency
+                }
+
+                /// Releases the TIM peripheral and the channel pin
+                pub fn free(self) -> ($TIM, PIN) {
+                    // pause counter
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    (self.tim, self.pin)
+                }
+            }
+        )+
+    }
+}
+
+hal! {
+    TIM2: (tim2, APB1, tim2en, tim2rst, pclk1, ppre1, u32, [
+        (0, ccmr1_output, ccr1),
+        (1, ccmr1_output, ccr2),
+        (2, ccmr2_output, ccr3),
+        (3, ccmr2_output, ccr4),
+    ]),
+    TIM3: (tim3, APB1, tim3en, tim3rst, pclk1, ppre1, u16, [
+        (0, ccmr1_output, ccr1),
+        (1, ccmr1_output, ccr2),
+        (2, ccmr2_output, ccr3),
+        (3, ccmr2_output, ccr4),
+    ]),
+    TIM4: (tim4, APB1, tim4en, tim4rst, pclk1, ppre1, u16, [
+        (0, ccmr1_output, ccr1),
+        (1, ccmr1_output, ccr2),
+        (2, ccmr2_output, ccr3),
+        (3, ccmr2_output, ccr4),
+    ]),
+    TIM15: (tim15, APB2, tim15en, tim15rst, pclk2, ppre2, u16, [
+        (0, ccmr1_output, ccr1),
+        (1, ccmr1_output, ccr2),
+    ]),
+    TIM16: (tim16, APB2, tim16en, tim16rst, pclk2, ppre2, u16, [
+        (0, ccmr1_output, ccr1),
+    ]),
+    TIM17: (tim17, APB2, tim17en, tim17rst, pclk2, ppre2, u16, [
+        (0, ccmr1_output, ccr1),
+    ]),
+}
+
+pwm! {
+    TIM2: (tim2, APB1, tim2en, tim2rst, pclk1, ppre1, u32),
+    TIM3: (tim3, APB1, tim3en, tim3rst, pclk1, ppre1, u16),
+    TIM4: (tim4, APB1, tim4en, tim4rst, pclk1, ppre1, u16),
+    TIM15: (tim15, APB2, tim15en, tim15rst, pclk2, ppre2, u16),
+}
@@ -0,0 +1,507 @@
+//! Direct Memory Access (DMA)
+//!
+//! `DmaExt::split` turns the DMA1 and DMA2 peripherals into owned channel handles. A channel is
+//! moved into a `Transfer` (or a `CircBuffer`) together with the buffer and the peripheral it
+//! serves and is handed back once the transfer is over. The buffers are `'static` so the memory
+//! can't be freed or reused while the DMA is still accessing it.
+
+use core::marker::PhantomData;
+
+use rcc::AHB;
+
+/// DMA error
+#[derive(Debug)]
+pub enum Error {
+    /// The DMA overwrote a half of a circular buffer that was still being read
+    Overrun,
+    /// A bus error aborted the transfer
+    Transfer,
+    /// The peripheral reported an error that stopped the transfer, e.g. a serial overrun
+    Peripheral,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Interrupt event
+pub enum Event {
+    /// The first half of the buffer has been transferred
+    HalfTransfer,
+    /// The whole buffer has been transferred
+    TransferComplete,
+}
+
+/// Half of a circular buffer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Half {
+    /// First half
+    First,
+    /// Second half
+    Second,
+}
+
+/// Read transfer: the DMA moves data from a peripheral into memory
+pub struct R;
+
+/// Write transfer: the DMA moves data from memory into a peripheral
+pub struct W;
+
+/// A circular buffer that the DMA continuously fills, one half at a time
+pub struct CircBuffer<BUFFER, PAYLOAD>
+where
+    BUFFER: 'static,
+{
+    buffer: &'static mut [BUFFER; 2],
+    payload: PAYLOAD,
+    readable_half: Half,
+}
+
+impl<BUFFER, PAYLOAD> CircBuffer<BUFFER, PAYLOAD> {
+    pub(crate) fn new(buffer: &'static mut [BUFFER; 2], payload: PAYLOAD) -> Self {
+        CircBuffer {
+            buffer,
+            payload,
+            readable_half: Half::Second,
+        }
+    }
+}
+
+/// An on-going DMA transfer
+pub struct Transfer<MODE, BUFFER, PAYLOAD> {
+    _mode: PhantomData<MODE>,
+    buffer: BUFFER,
+    payload: PAYLOAD,
+}
+
+impl<BUFFER, PAYLOAD> Transfer<R, BUFFER, PAYLOAD> {
+    pub(crate) fn r(buffer: BUFFER, payload: PAYLOAD) -> Self {
+        Transfer {
+            _mode: PhantomData,
+            buffer,
+            payload,
+        }
+    }
+}
+
+impl<BUFFER, PAYLOAD> Transfer<W, BUFFER, PAYLOAD> {
+    pub(crate) fn w(buffer: BUFFER, payload: PAYLOAD) -> Self {
+        Transfer {
+            _mode: PhantomData,
+            buffer,
+            payload,
+        }
+    }
+}
+
+/// A peripheral receiving data through a DMA channel
+pub struct RxDma<PAYLOAD, CHANNEL> {
+    pub(crate) payload: PAYLOAD,
+    /// The DMA channel
+    pub channel: CHANNEL,
+}
+
+impl<PAYLOAD, CHANNEL> RxDma<PAYLOAD, CHANNEL> {
+    /// Releases the peripheral and the DMA channel
+    pub fn free(self) -> (PAYLOAD, CHANNEL) {
+        (self.payload, self.channel)
+    }
+}
+
+/// A peripheral transmitting data through a DMA channel
+pub struct TxDma<PAYLOAD, CHANNEL> {
+    pub(crate) payload: PAYLOAD,
+    /// The DMA channel
+    pub channel: CHANNEL,
+}
+
+impl<PAYLOAD, CHANNEL> TxDma<PAYLOAD, CHANNEL> {
+    /// Releases the peripheral and the DMA channel
+    pub fn free(self) -> (PAYLOAD, CHANNEL) {
+        (self.payload, self.channel)
+    }
+}
+
+/// Peripheral side of a DMA transfer
+pub trait TransferPayload {
+    /// Starts the transfer
+    fn start(&mut self);
+
+    /// Stops the transfer
+    fn stop(&mut self);
+
+    /// Returns the error that stopped the peripheral side of the transfer, if any
+    fn error(&self) -> Option<Error>;
+
+    /// Returns `true` once the peripheral is done with all the data the DMA moved
+    fn is_idle(&self) -> bool;
+}
+
+/// Extension trait to split a DMA peripheral into independent channels
+pub trait DmaExt {
+    /// The channels to split the DMA into
+    type Channels;
+
+    /// Splits the DMA peripheral into independent channels
+    fn split(self, ahb: &mut AHB) -> Self::Channels;
+}
+
+macro_rules! dma {
+    ($($DMAX:ident: ($dmaX:ident, $dmaXen:ident, {
+        $($CX:ident: (
+            $ccrX:ident,
+            $CCRX:ident,
+            $cndtrX:ident,
+            $cparX:ident,
+            $cmarX:ident,
+            $htifX:ident,
+            $tcifX:ident,
+            $teifX:ident,
+            $chtifX:ident,
+            $ctcifX:ident,
+            $cgifX:ident
+        ),)+
+    }),)+) => {
+        $(
+            /// DMA channels
+            pub mod $dmaX {
+                use core::sync::atomic::{self, Ordering};
+
+                use stm32f30x::{dma1, $DMAX};
+
+                use dma::{CircBuffer, DmaExt, Error, Event, Half, RxDma, Transfer, TransferPayload,
+                          TxDma};
+                use rcc::AHB;
+
+                /// DMA channels
+                pub struct Channels((), $(pub $CX),+);
+
+                $(
+                    /// A DMA channel
+                    pub struct $CX { _0: () }
+
+                    impl $CX {
+                        /// Sets the address of the peripheral register the data is moved to or
+                        /// from; `inc` increments the address after each transferred item
+                        pub fn set_peripheral_address(&mut self, address: u32, inc: bool) {
+                            // NOTE(unsafe) this channel has exclusive access to its registers
+                            unsafe { (*$DMAX::ptr()).$cparX.write(|w| w.bits(address)) }
+                            self.ccr().modify(|_, w| w.pinc().bit(inc));
+                        }
+
+                        /// Sets the address of the memory the data is moved to or from; `inc`
+                        /// increments the address after each transferred item
+                        pub fn set_memory_address(&mut self, address: u32, inc: bool) {
+                            // NOTE(unsafe) this channel has exclusive access to its registers
+                            unsafe { (*$DMAX::ptr()).$cmarX.write(|w| w.bits(address)) }
+                            self.ccr().modify(|_, w| w.minc().bit(inc));
+                        }
+
+                        /// Sets the number of items to transfer
+                        ///
+                        /// # Panics
+                        ///
+                        /// If `len` doesn't fit in 16 bits
+                        pub fn set_transfer_length(&mut self, len: usize) {
+                            assert!(len <= 0xffff, "transfer too long");
+                            // NOTE(unsafe) this channel has exclusive access to its registers
+                            unsafe { (*$DMAX::ptr()).$cndtrX.write(|w| w.bits(len as u32)) }
+                        }
+
+                        /// Enables the channel; the transfer starts as soon as the peripheral
+                        /// requests it
+                        pub fn start(&mut self) {
+                            self.ccr().modify(|_, w| w.en().set_bit());
+                        }
+
+                        /// Disables the channel and clears its flags
+                        pub fn stop(&mut self) {
+                            self.ifcr().write(|w| w.$cgifX().set_bit());
+                            self.ccr().modify(|_, w| w.en().clear_bit());
+                        }
+
+                        /// Returns `true` until the whole buffer has been transferred
+                        ///
+                        /// A transfer error also ends the transfer; see `has_transfer_error`
+                        pub fn in_progress(&self) -> bool {
+                            self.isr().$tcifX().bit_is_clear()
+                        }
+
+                        /// Returns `true` if a bus error aborted the transfer and disabled the
+                        /// channel
+                        pub fn has_transfer_error(&self) -> bool {
+                            self.isr().$teifX().bit_is_set()
+                        }
+
+                        /// Starts listening for an interrupt `event`
+                        pub fn listen(&mut self, event: Event) {
+                            match event {
+                                Event::HalfTransfer => {
+                                    self.ccr().modify(|_, w| w.htie().set_bit())
+                                }
+                                Event::TransferComplete => {
+                                    self.ccr().modify(|_, w| w.tcie().set_bit())
+                                }
+                            }
+                        }
+
+                        /// Stops listening for an interrupt `event`
+                        pub fn unlisten(&mut self, event: Event) {
+                            match event {
+                                Event::HalfTransfer => {
+                                    self.ccr().modify(|_, w| w.htie().clear_bit())
+                                }
+                                Event::TransferComplete => {
+                                    self.ccr().modify(|_, w| w.tcie().clear_bit())
+                                }
+                            }
+                        }
+
+                        /// Returns the number of items that remain to be transferred
+                        pub fn remaining(&self) -> u16 {
+                            // NOTE(unsafe) atomic read with no side effects
+                            unsafe { (*$DMAX::ptr()).$cndtrX.read().ndt().bits() }
+                        }
+
+                        pub(crate) fn ccr(&mut self) -> &dma1::$CCRX {
+                            // NOTE(unsafe) this channel has exclusive access to its registers
+                            unsafe { &(*$DMAX::ptr()).$ccrX }
+                        }
+
+                        fn isr(&self) -> dma1::isr::R {
+                            // NOTE(unsafe) atomic read with no side effects
+                            unsafe { (*$DMAX::ptr()).isr.read() }
+                        }
+
+                        fn ifcr(&self) -> &dma1::IFCR {
+                            // NOTE(unsafe) write-to-clear register; this channel only writes
+                            // ones to its own flags
+                            unsafe { &(*$DMAX::ptr()).ifcr }
+                        }
+                    }
+
+                    impl<B, PAYLOAD> CircBuffer<B, RxDma<PAYLOAD, $CX>> {
+                        /// Peeks into the readable half of the buffer
+                        ///
+                        /// Returns an error if the DMA overwrote that half while `f` was reading
+                        /// it
+                        pub fn peek<R, F>(&mut self, f: F) -> Result<R, Error>
+                        where
+                            F: FnOnce(&B, Half) -> R,
+                        {
+                            let half_being_read = self.readable_half()?;
+
+                            let buf = match half_being_read {
+                                Half::First => &self.buffer[0],
+                                Half::Second => &self.buffer[1],
+                            };
+
+                            // the data must be read after the DMA flags
+                            atomic::compiler_fence(Ordering::Acquire);
+
+                            let ret = f(buf, half_being_read);
+
+                            let isr = self.payload.channel.isr();
+                            let first_half_is_done = isr.$htifX().bit_is_set();
+                            let second_half_is_done = isr.$tcifX().bit_is_set();
+
+                            if (half_being_read == Half::First && second_half_is_done)
+                                || (half_being_read == Half::Second && first_half_is_done)
+                            {
+                                Err(Error::Overrun)
+                            } else {
+                                Ok(ret)
+                            }
+                        }
+
+                        /// Returns the `Half` of the buffer that can be read
+                        ///
+                        /// Returns an error if the DMA filled both halves since the last call or
+                        /// if a bus error aborted the transfer
+                        pub fn readable_half(&mut self) -> Result<Half, Error> {
+                            if self.payload.channel.has_transfer_error() {
+                                return Err(Error::Transfer);
+                            }
+
+                            let isr = self.payload.channel.isr();
+                            let first_half_is_done = isr.$htifX().bit_is_set();
+                            let second_half_is_done = isr.$tcifX().bit_is_set();
+
+                            if first_half_is_done && second_half_is_done {
+                                return Err(Error::Overrun);
+                            }
+
+                            let last_read_half = self.readable_half;
+
+                            Ok(match last_read_half {
+                                Half::First => {
+                                    if second_half_is_done {
+                                        self.payload
+                                            .channel
+                                            .ifcr()
+                                            .write(|w| w.$ctcifX().set_bit());
+
+                                        self.readable_half = Half::Second;
+                                        Half::Second
+                                    } else {
+                                        last_read_half
+                                    }
+                                }
+                                Half::Second => {
+                                    if first_half_is_done {
+                                        self.payload
+                                            .channel
+                                            .ifcr()
+                                            .write(|w| w.$chtifX().set_bit());
+
+                                        self.readable_half = Half::First;
+                                        Half::First
+                                    } else {
+                                        last_read_half
+                                    }
+                                }
+                            })
+                        }
+                    }
+
+                    impl<B, PAYLOAD> CircBuffer<B, RxDma<PAYLOAD, $CX>>
+                    where
+                        RxDma<PAYLOAD, $CX>: TransferPayload,
+                    {
+                        /// Stops the transfer and returns the buffer and the payload
+                        pub fn stop(mut self) -> (&'static mut [B; 2], RxDma<PAYLOAD, $CX>) {
+                            self.payload.stop();
+
+                            (self.buffer, self.payload)
+                        }
+                    }
+
+                    impl<BUFFER, PAYLOAD, MODE> Transfer<MODE, BUFFER, RxDma<PAYLOAD, $CX>>
+                    where
+                        RxDma<PAYLOAD, $CX>: TransferPayload,
+                    {
+                        /// Returns `true` once the whole buffer has been transferred and the
+                        /// peripheral is done with it
+                        ///
+                        /// Returns an error if the transfer failed
+                        pub fn is_done(&self) -> Result<bool, Error> {
+                            if self.payload.channel.has_transfer_error() {
+                                Err(Error::Transfer)
+                            } else if let Some(error) = self.payload.error() {
+                                Err(error)
+                            } else {
+                                Ok(!self.payload.channel.in_progress() && self.payload.is_idle())
+                            }
+                        }
+
+                        /// Blocks until the transfer is done or fails and returns the buffer and
+                        /// the payload, along with the error if it failed
+                        pub fn wait(
+                            mut self,
+                        ) -> Result<
+                            (BUFFER, RxDma<PAYLOAD, $CX>),
+                            (Error, BUFFER, RxDma<PAYLOAD, $CX>),
+                        > {
+                            let result = loop {
+                                match self.is_done() {
+                                    Ok(true) => break Ok(()),
+                                    Ok(false) => {}
+                                    Err(error) => break Err(error),
+                                }
+                            };
+
+                            // the buffer must be accessed after the DMA is done with it
+                            atomic::compiler_fence(Ordering::Acquire);
+
+                            self.payload.stop();
+
+                            match result {
+                                Ok(()) => Ok((self.buffer, self.payload)),
+                                Err(error) => Err((error, self.buffer, self.payload)),
+                            }
+                        }
+                    }
+
+                    impl<BUFFER, PAYLOAD, MODE> Transfer<MODE, BUFFER, TxDma<PAYLOAD, $CX>>
+                    where
+                        TxDma<PAYLOAD, $CX>: TransferPayload,
+                    {
+                        /// Returns `true` once the whole buffer has been transferred and the
+                        /// peripheral is done with it
+                        ///
+                        /// Returns an error if the transfer failed
+                        pub fn is_done(&self) -> Result<bool, Error> {
+                            if self.payload.channel.has_transfer_error() {
+                                Err(Error::Transfer)
+                            } else if let Some(error) = self.payload.error() {
+                                Err(error)
+                            } else {
+                                Ok(!self.payload.channel.in_progress() && self.payload.is_idle())
+                            }
+                        }
+
+                        /// Blocks until the transfer is done or fails and returns the buffer and
+                        /// the payload, along with the error if it failed
+                        pub fn wait(
+                            mut self,
+                        ) -> Result<
+                            (BUFFER, TxDma<PAYLOAD, $CX>),
+                            (Error, BUFFER, TxDma<PAYLOAD, $CX>),
+                        > {
+                            let result = loop {
+                                match self.is_done() {
+                                    Ok(true) => break Ok(()),
+                                    Ok(false) => {}
+                                    Err(error) => break Err(error),
+                                }
+                            };
+
+                            // the buffer must be accessed after the DMA is done with it
+                            atomic::compiler_fence(Ordering::Acquire);
+
+                            self.payload.stop();
+
+                            match result {
+                                Ok(()) => Ok((self.buffer, self.payload)),
+                                Err(error) => Err((error, self.buffer, self.payload)),
+                            }
+                        }
+                    }
+                )+
+
+                impl DmaExt for $DMAX {
+                    type Channels = Channels;
+
+                    fn split(self, ahb: &mut AHB) -> Channels {
+                        ahb.enr().modify(|_, w| w.$dmaXen().set_bit());
+
+                        // reset the channel configurations; this stops all on-going transfers
+                        $(
+                            self.$ccrX.reset();
+                        )+
+
+                        Channels((), $($CX { _0: () }),+)
+                    }
+                }
+            }
+        )+
+    }
+}
+
+dma! {
+    DMA1: (dma1, dmaen, {
+        C1: (ccr1, CCR1, cndtr1, cpar1, cmar1, htif1, tcif1, teif1, chtif1, ctcif1, cgif1),
+        C2: (ccr2, CCR2, cndtr2, cpar2, cmar2, htif2, tcif2, teif2, chtif2, ctcif2, cgif2),
+        C3: (ccr3, CCR3, cndtr3, cpar3, cmar3, htif3, tcif3, teif3, chtif3, ctcif3, cgif3),
+        C4: (ccr4, CCR4, cndtr4, cpar4, cmar4, htif4, tcif4, teif4, chtif4, ctcif4, cgif4),
+        C5: (ccr5, CCR5, cndtr5, cpar5, cmar5, htif5, tcif5, teif5, chtif5, ctcif5, cgif5),
+        C6: (ccr6, CCR6, cndtr6, cpar6, cmar6, htif6, tcif6, teif6, chtif6, ctcif6, cgif6),
+        C7: (ccr7, CCR7, cndtr7, cpar7, cmar7, htif7, tcif7, teif7, chtif7, ctcif7, cgif7),
+    }),
+
+    DMA2: (dma2, dma2en, {
+        C1: (ccr1, CCR1, cndtr1, cpar1, cmar1, htif1, tcif1, teif1, chtif1, ctcif1, cgif1),
+        C2: (ccr2, CCR2, cndtr2, cpar2, cmar2, htif2, tcif2, teif2, chtif2, ctcif2, cgif2),
+        C3: (ccr3, CCR3, cndtr3, cpar3, cmar3, htif3, tcif3, teif3, chtif3, ctcif3, cgif3),
+        C4: (ccr4, CCR4, cndtr4, cpar4, cmar4, htif4, tcif4, teif4, chtif4, ctcif4, cgif4),
+        C5: (ccr5, CCR5, cndtr5, cpar5, cmar5, htif5, tcif5, teif5, chtif5, ctcif5, cgif5),
+    }),
+}
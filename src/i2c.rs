@@ -10,6 +10,7 @@ use gpio::AF4;
 use hal::blocking::i2c::{Write, WriteRead};
 use rcc::{APB1, Clocks};
 use time::Hertz;
+use timeout::{self, Deadline, Never};
 
 /// I2C error
 #[derive(Debug)]
@@ -55,16 +56,25 @@ pub struct I2c<I2C, PINS> {
 }
 
 macro_rules! busy_wait {
-    ($i2c:expr, $flag:ident) => {
+    ($i2c:expr, $flag:ident, $deadline:expr) => {
         loop {
             let isr = $i2c.isr.read();
 
             if isr.berr().bit_is_set() {
-                return Err(Error::Bus);
+                return Err(timeout::Error::Other(Error::Bus));
             } else if isr.arlo().bit_is_set() {
-                return Err(Error::Arbitration);
+                return Err(timeout::Error::Other(Error::Arbitration));
             } else if isr.$flag().bit_is_set() {
                 break;
+            } else if $deadline.expired() {
+                // abort the transfer in progress with a software reset; this releases the lines
+                // and leaves the peripheral ready for the next transfer
+                $i2c.cr1.modify(|_, w| w.pe().clear_bit());
+                // NOTE PE must stay low for at least 3 APB cycles
+                while $i2c.cr1.read().pe().bit_is_set() {}
+                $i2c.cr1.modify(|_, w| w.pe().set_bit());
+
+                return Err(timeout::Error::TimedOut);
             } else {
                 // try again
             }
@@ -72,6 +82,13 @@ macro_rules! busy_wait {
     };
 }
 
+fn never_timed_out(e: timeout::Error<Error>) -> Error {
+    match e {
+        timeout::Error::Other(e) => e,
+        timeout::Error::TimedOut => unreachable!(),
+    }
+}
+
 macro_rules! hal {
     ($($I2CX:ident: ($i2cX:ident, $i2cXen:ident, $i2cXrst:ident),)+) => {
         $(
@@ -178,10 +195,19 @@ macro_rules! hal {
                 }
             }
 
-            impl<PINS> Write for I2c<$I2CX, PINS> {
-                type Error = Error;
-
-                fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
+            impl<PINS> I2c<$I2CX, PINS> {
+                /// Like `Write::write` but gives up once `deadline` expires
+                ///
+                /// On timeout the transfer is aborted with a software reset of the peripheral
+                pub fn write_with_deadline<D>(
+                    &mut self,
+                    addr: u8,
+                    bytes: &[u8],
+                    deadline: &mut D,
+                ) -> Result<(), timeout::Error<Error>>
+                where
+                    D: Deadline,
+                {
                     // TODO support transfers of more than 255 bytes
                     assert!(bytes.len() < 256 && bytes.len() > 0);
 
@@ -202,30 +228,33 @@ macro_rules! hal {
                     for byte in bytes {
                         // Wait until we are allowed to send data (START has been ACKed or last byte
                         // when through)
-                        busy_wait!(self.i2c, txis);
+                        busy_wait!(self.i2c, txis, deadline);
 
                         // put byte on the wire
                         self.i2c.txdr.write(|w| w.txdata().bits(*byte));
                     }
 
                     // Wait until the last transmission is finished ???
-                    // busy_wait!(self.i2c, busy);
+                    // busy_wait!(self.i2c, busy, deadline);
 
                     // automatic STOP
 
                     Ok(())
                 }
-            }
-
-            impl<PINS> WriteRead for I2c<$I2CX, PINS> {
-                type Error = Error;
 
-                fn write_read(
+                /// Like `WriteRead::write_read` but gives up once `deadline` expires
+                ///
+                /// On timeout the transfer is aborted with a software reset of the peripheral
+                pub fn write_read_with_deadline<D>(
                     &mut self,
                     addr: u8,
                     bytes: &[u8],
                     buffer: &mut [u8],
-                ) -> Result<(), Error> {
+                    deadline: &mut D,
+                ) -> Result<(), timeout::Error<Error>>
+                where
+                    D: Deadline,
+                {
                     // TODO support transfers of more than 255 bytes
                     assert!(bytes.len() < 256 && bytes.len() > 0);
                     assert!(buffer.len() < 256 && buffer.len() > 0);
@@ -250,14 +279,14 @@ macro_rules! hal {
                     for byte in bytes {
                         // Wait until we are allowed to send data (START has been ACKed or last byte
                         // when through)
-                        busy_wait!(self.i2c, txis);
+                        busy_wait!(self.i2c, txis, deadline);
 
                         // put byte on the wire
                         self.i2c.txdr.write(|w| w.txdata().bits(*byte));
                     }
 
                     // Wait until the last transmission is finished
-                    busy_wait!(self.i2c, tc);
+                    busy_wait!(self.i2c, tc, deadline);
 
                     // reSTART and prepare to receive bytes into `buffer`
                     self.i2c.cr2.write(|w| {
@@ -275,7 +304,7 @@ macro_rules! hal {
 
                     for byte in buffer {
                         // Wait until we have received something
-                        busy_wait!(self.i2c, rxne);
+                        busy_wait!(self.i2c, rxne, deadline);
 
                         *byte = self.i2c.rxdr.read().rxdata().bits();
                     }
@@ -285,6 +314,29 @@ macro_rules! hal {
                     Ok(())
                 }
             }
+
+            impl<PINS> Write for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
+                    self.write_with_deadline(addr, bytes, &mut Never)
+                        .map_err(never_timed_out)
+                }
+            }
+
+            impl<PINS> WriteRead for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn write_read(
+                    &mut self,
+                    addr: u8,
+                    bytes: &[u8],
+                    buffer: &mut [u8],
+                ) -> Result<(), Error> {
+                    self.write_read_with_deadline(addr, bytes, buffer, &mut Never)
+                        .map_err(never_timed_out)
+                }
+            }
         )+
     }
 }
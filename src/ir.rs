@@ -0,0 +1,140 @@
+//! Infrared transmitter (IRTIM)
+//!
+//! The IR_OUT signal combines two timers: TIM17 channel 1 generates the carrier and TIM16 channel
+//! 1 generates the envelope, i.e. the marks (carrier on) and spaces (carrier off) of the encoded
+//! frame.
+
+use core::cmp;
+
+use cast::u16;
+use stm32f30x::{TIM16, TIM17};
+
+use gpio::gpiob::PB9;
+use gpio::AF6;
+use rcc::{APB2, Clocks};
+use time::Hertz;
+
+// OC1M values
+const FORCE_INACTIVE: u32 = 0b100;
+const FORCE_ACTIVE: u32 = 0b101;
+const PWM1: u32 = 0b110;
+
+// FIXME these should be "closed" traits
+/// IR_OUT pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait IrOutPin {}
+
+// unsafe impl IrOutPin for PA13<AF5> {}
+unsafe impl IrOutPin for PB9<AF6> {}
+
+/// Infrared transmitter
+pub struct IrTransmitter<PIN> {
+    envelope: TIM16,
+    carrier: TIM17,
+    pin: PIN,
+}
+
+impl<PIN> IrTransmitter<PIN> {
+    /// Configures TIM16 and TIM17 to output a `carrier` modulated signal on the IR_OUT `pin`
+    ///
+    /// The carrier has a 50% duty cycle. The envelope timer counts microseconds.
+    pub fn new<F>(
+        envelope: TIM16,
+        carrier: TIM17,
+        pin: PIN,
+        frequency: F,
+        clocks: Clocks,
+        apb2: &mut APB2,
+    ) -> Self
+    where
+        F: Into<Hertz>,
+        PIN: IrOutPin,
+    {
+        // enable and reset peripherals to a clean slate state
+        apb2.enr()
+            .modify(|_, w| w.tim16en().set_bit().tim17en().set_bit());
+        apb2.rstr()
+            .modify(|_, w| w.tim16rst().set_bit().tim17rst().set_bit());
+        apb2.rstr()
+            .modify(|_, w| w.tim16rst().clear_bit().tim17rst().clear_bit());
+
+        let timclk = clocks.pclk2().0 * if clocks.ppre2() == 1 { 1 } else { 2 };
+
+        // carrier: free running PWM
+        let arr = timclk / frequency.into().0;
+        assert!(arr >= 2, "carrier frequency too high");
+        let arr = u16(arr - 1).expect("carrier frequency too low");
+
+        carrier.arr.write(|w| unsafe { w.arr().bits(arr) });
+        carrier.ccr1.write(|w| unsafe { w.ccr1().bits(arr / 2 + 1) });
+        // OC1M: PWM mode 1
+        carrier
+            .ccmr1_output
+            .write(|w| unsafe { w.bits(PWM1 << 4) });
+        // CC1E: enable the output
+        carrier.ccer.write(|w| w.cc1e().set_bit());
+        // MOE: main output enable
+        carrier.bdtr.write(|w| w.moe().set_bit());
+        carrier.cr1.write(|w| w.cen().set_bit());
+
+        // envelope: one microsecond ticks
+        assert!(timclk >= 1_000_000, "timer clock too low");
+        let psc = u16(timclk / 1_000_000 - 1).unwrap();
+        envelope.psc.write(|w| unsafe { w.psc().bits(psc) });
+        // OPM: the counter stops at the end of each mark or space
+        // URS: only counter overflows raise the update flag
+        envelope.cr1.write(|w| w.opm().set_bit().urs().set_bit());
+        // UG: load the prescaler value
+        envelope.egr.write(|w| w.ug().set_bit());
+        // OC1M: carrier off
+        envelope
+            .ccmr1_output
+            .write(|w| unsafe { w.bits(FORCE_INACTIVE << 4) });
+        envelope.ccer.write(|w| w.cc1e().set_bit());
+        envelope.bdtr.write(|w| w.moe().set_bit());
+
+        IrTransmitter {
+            envelope,
+            carrier,
+            pin,
+        }
+    }
+
+    /// Sends a sequence of alternating marks and spaces, starting with a mark
+    ///
+    /// The durations are in microseconds. This blocks until the sequence has been sent; the
+    /// carrier is off afterwards. Zero durations are skipped; 1 microsecond durations last 2
+    /// microseconds, the shortest mark or space the envelope timer can time.
+    pub fn send(&mut self, pulses: &[u16]) {
+        for (i, &us) in pulses.iter().enumerate() {
+            let mode = if i % 2 == 0 { FORCE_ACTIVE } else { FORCE_INACTIVE };
+            self.envelope
+                .ccmr1_output
+                .write(|w| unsafe { w.bits(mode << 4) });
+
+            if us == 0 {
+                continue;
+            }
+
+            // NOTE the counter runs from 0 to ARR, both included; ARR = 0 would stop it
+            let arr = cmp::max(us - 1, 1);
+            self.envelope.arr.write(|w| unsafe { w.arr().bits(arr) });
+            self.envelope.cr1.modify(|_, w| w.cen().set_bit());
+
+            while self.envelope.sr.read().uif().bit_is_clear() {}
+
+            // NOTE(unsafe) the SR flags are cleared by writing zero; ones are ignored
+            self.envelope.sr.write(|w| unsafe { w.bits(!1) });
+        }
+
+        self.envelope
+            .ccmr1_output
+            .write(|w| unsafe { w.bits(FORCE_INACTIVE << 4) });
+    }
+
+    /// Releases the TIM peripherals and the IR_OUT pin
+    pub fn free(self) -> (TIM16, TIM17, PIN) {
+        // pause carrier
+        self.carrier.cr1.modify(|_, w| w.cen().clear_bit());
+        (self.envelope, self.carrier, self.pin)
+    }
+}
@@ -34,13 +34,19 @@ extern crate nb;
 pub extern crate stm32f30x;
 extern crate void;
 
+pub mod capture;
 pub mod delay;
+pub mod dma;
 pub mod flash;
 pub mod gpio;
 pub mod i2c;
+pub mod ir;
+pub mod monotonic;
+pub mod opm;
 pub mod prelude;
 pub mod rcc;
 pub mod serial;
 pub mod spi;
 pub mod time;
+pub mod timeout;
 pub mod timer;
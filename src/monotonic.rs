@@ -0,0 +1,246 @@
+//! Monotonic clock based on the 32-bit TIM2 counter
+//!
+//! Unlike `time::MonoTimer`, which uses the DWT cycle counter, this clock keeps counting while the
+//! core is halted and is extended to 64 bits by counting the overflows of TIM2, so it never wraps
+//! around in practice.
+//!
+//! The overflow count is kept up to date by the TIM2 interrupt: the application must unmask the
+//! TIM2 interrupt in the NVIC and call `Monotonic::on_overflow` from its handler.
+
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use cast::{u16, u32, u64};
+use stm32f30x::TIM2;
+
+use rcc::{APB1, Clocks};
+use time::{Hertz, MicroSeconds};
+
+// number of TIM2 overflows, i.e. the upper 32 bits of the 64-bit counter
+static OVERFLOWS: AtomicUsize = AtomicUsize::new(0);
+
+/// A monotonic nondecreasing 64-bit clock driven by TIM2
+pub struct Monotonic {
+    frequency: Hertz,
+    tim: TIM2,
+}
+
+impl Monotonic {
+    /// Configures TIM2 as a free running counter that ticks at `resolution`
+    ///
+    /// The counter ticks at `resolution` or at the closest frequency the prescaler can achieve;
+    /// see `frequency`.
+    // NOTE(allow) `w.psc().bits()` is not safe for TIM2 due to some SVD omission
+    #[allow(unused_unsafe)]
+    pub fn tim2<F>(tim: TIM2, resolution: F, clocks: Clocks, apb1: &mut APB1) -> Self
+    where
+        F: Into<Hertz>,
+    {
+        // enable and reset peripheral to a clean slate state
+        apb1.enr().modify(|_, w| w.tim2en().set_bit());
+        apb1.rstr().modify(|_, w| w.tim2rst().set_bit());
+        apb1.rstr().modify(|_, w| w.tim2rst().clear_bit());
+
+        let timclk = clocks.pclk1().0 * if clocks.ppre1() == 1 { 1 } else { 2 };
+        let ticks = timclk / resolution.into().0;
+        assert!(ticks > 0, "resolution higher than the timer clock");
+
+        let psc = u16(ticks - 1).unwrap();
+        tim.psc.write(|w| unsafe { w.psc().bits(psc) });
+        tim.arr.write(|w| unsafe { w.bits(u32::max_value()) });
+
+        // URS: only counter overflows raise the update flag
+        tim.cr1.modify(|_, w| w.urs().set_bit());
+        // UG: load the new prescaler value
+        tim.egr.write(|w| w.ug().set_bit());
+
+        OVERFLOWS.store(0, Ordering::SeqCst);
+
+        // UIE: interrupt on overflow
+        tim.dier.modify(|_, w| w.uie().set_bit());
+
+        // start counter
+        tim.cr1.modify(|_, w| w.cen().set_bit());
+
+        Monotonic {
+            frequency: Hertz(timclk / (u32(psc) + 1)),
+            tim,
+        }
+    }
+
+    /// Accounts for an overflow of the TIM2 counter
+    ///
+    /// This must be called from the TIM2 interrupt handler while the `Monotonic` clock is in use.
+    pub fn on_overflow() {
+        // NOTE(unsafe) atomic read with no side effects
+        let tim = unsafe { &*TIM2::ptr() };
+
+        if tim.sr.read().uif().bit_is_set() {
+            // NOTE(unsafe) the SR flags are cleared by writing zero; ones are ignored
+            tim.sr.write(|w| unsafe { w.bits(!1) });
+            OVERFLOWS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Returns the frequency at which the clock ticks
+    pub fn frequency(&self) -> Hertz {
+        self.frequency
+    }
+
+    /// Returns an `Instant` corresponding to "now"
+    pub fn now(&self) -> Instant {
+        loop {
+            let hi = OVERFLOWS.load(Ordering::SeqCst);
+            let lo = self.tim.cnt.read().bits();
+            let pending = self.tim.sr.read().uif().bit_is_set();
+
+            if hi != OVERFLOWS.load(Ordering::SeqCst) {
+                // the overflow interrupt ran in between; try again
+                continue;
+            }
+
+            // NOTE if the overflow interrupt couldn't run yet (e.g. we are being called from an
+            // interrupt handler with higher priority) `lo` may already have wrapped around
+            // NOTE(as) lossless: `usize` is 32 bits wide on this target
+            let hi = hi as u64 + if pending && lo < (1 << 31) { 1 } else { 0 };
+
+            return Instant {
+                ticks: (hi << 32) | u64(lo),
+            };
+        }
+    }
+
+    /// Converts a time span into a `Duration`, rounding down to a whole number of ticks
+    pub fn duration<T>(&self, span: T) -> Duration
+    where
+        T: Into<MicroSeconds>,
+    {
+        Duration {
+            ticks: u64(span.into().0) * u64(self.frequency.0) / 1_000_000,
+        }
+    }
+
+    /// Releases the TIM2 peripheral
+    pub fn free(self) -> TIM2 {
+        // pause counter
+        self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+        self.tim.dier.modify(|_, w| w.uie().clear_bit());
+        self.tim
+    }
+}
+
+/// A measurement of the `Monotonic` clock
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Instant {
+    ticks: u64,
+}
+
+impl Instant {
+    /// Returns the number of ticks elapsed since the `Monotonic` clock was started
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Returns the time elapsed from `earlier` to `self`
+    ///
+    /// # Panics
+    ///
+    /// If `earlier` is later than `self`
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration {
+            ticks: self.ticks
+                .checked_sub(earlier.ticks)
+                .expect("`earlier` is later than `self`"),
+        }
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant {
+            ticks: self.ticks + rhs.ticks,
+        }
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        self.ticks += rhs.ticks;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        Instant {
+            ticks: self.ticks - rhs.ticks,
+        }
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        self.ticks -= rhs.ticks;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// A span of time measured in ticks of the `Monotonic` clock
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Duration {
+    ticks: u64,
+}
+
+impl Duration {
+    /// Creates a `Duration` from a number of ticks
+    pub fn from_ticks(ticks: u64) -> Self {
+        Duration { ticks }
+    }
+
+    /// Returns the number of ticks in this `Duration`
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Duration {
+        Duration {
+            ticks: self.ticks + rhs.ticks,
+        }
+    }
+}
+
+impl AddAssign for Duration {
+    fn add_assign(&mut self, rhs: Duration) {
+        self.ticks += rhs.ticks;
+    }
+}
+
+impl Sub for Duration {
+    type Output = Duration;
+
+    fn sub(self, rhs: Duration) -> Duration {
+        Duration {
+            ticks: self.ticks - rhs.ticks,
+        }
+    }
+}
+
+impl SubAssign for Duration {
+    fn sub_assign(&mut self, rhs: Duration) {
+        self.ticks -= rhs.ticks;
+    }
+}
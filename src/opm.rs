@@ -0,0 +1,362 @@
+//! One pulse mode
+//!
+//! Generates a single pulse on a timer channel, a programmable delay after a trigger. The pulse is
+//! fired by software (`trigger`) or, on TIM2, TIM3, TIM4 and TIM15, by an edge on the input of
+//! channel 1 or 2 (`with_trigger`, `with_retrigger`).
+
+use core::cmp;
+use core::marker::PhantomData;
+
+use cast::{u32, u64};
+use stm32f30x::{TIM15, TIM16, TIM17, TIM2, TIM3, TIM4};
+
+use capture::Edge;
+use rcc::{APB1, APB2, Clocks};
+use time::{Hertz, MicroSeconds};
+use timer::{CPin, Channel, Error, Period, TriggerChannel};
+
+/// One pulse mode on a timer channel
+pub struct OnePulse<TIM, CHANNEL, PINS> {
+    tim: TIM,
+    pins: PINS,
+    // timer clock, in Hz
+    clock: u32,
+    // in microseconds
+    delay: u32,
+    width: u32,
+    retriggerable: bool,
+    _channel: PhantomData<CHANNEL>,
+}
+
+// Computes the PSC, CCR and ARR values of a pulse given in microseconds
+fn timings(
+    clock: u32,
+    delay: u32,
+    width: u32,
+    retriggerable: bool,
+    arr_max: u32,
+) -> Result<(u16, u32, u32), Error> {
+    // NOTE in retriggerable mode the pulse starts right away and CCRx must be zero
+    let delay = if retriggerable { 0 } else { delay };
+
+    // the pulse ends when the counter overflows
+    let end: Period = MicroSeconds(delay.checked_add(width).ok_or(Error::PeriodTooLong)?).into();
+    let (psc, arr, _) = end.psc_arr(Hertz(clock), arr_max)?;
+
+    // in cycles of the timer clock, rounded to the nearest integer
+    let start = (u64(delay) * u64(clock) + 500_000) / 1_000_000;
+
+    let cycles = u64(psc) + 1;
+    let mut ccr = (start + cycles / 2) / cycles;
+    if !retriggerable {
+        // with CCRx = 0 the output would stay active after the pulse
+        ccr = cmp::max(ccr, 1);
+    }
+
+    if u64(arr) < ccr {
+        return Err(Error::PeriodTooShort);
+    }
+
+    // NOTE(as) `ccr` <= `arr`
+    Ok((psc, ccr as u32, arr))
+}
+
+macro_rules! hal {
+    ($($TIM:ident: (
+        $tim:ident,
+        $APB:ident,
+        $timXen:ident,
+        $timXrst:ident,
+        $pclkX:ident,
+        $ppreX:ident,
+        $width:ident,
+        [$(($i:pat, $ccmrX:ident, $ccrX:ident),)+]
+        $(, $bdtr:ident)*
+    ),)+) => {
+        $(
+            impl<CHANNEL, PIN> OnePulse<$TIM, CHANNEL, PIN>
+            where
+                CHANNEL: Channel,
+            {
+                /// Configures a TIM channel to output a pulse of `width` after `delay`
+                ///
+                /// The pulse is fired by calling `trigger`.
+                ///
+                /// # Panics
+                ///
+                /// If the timer can't achieve the pulse; see `set_pulse`
+                pub fn $tim<D, W>(
+                    tim: $TIM,
+                    pin: PIN,
+                    delay: D,
+                    width: W,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    D: Into<MicroSeconds>,
+                    W: Into<MicroSeconds>,
+                    PIN: CPin<$TIM, CHANNEL>,
+                {
+                    // enable and reset peripheral to a clean slate state
+                    apb.enr().modify(|_, w| w.$timXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().clear_bit());
+
+                    // OPM: the counter stops at the end of the pulse
+                    // URS: only counter overflows raise the update flag
+                    tim.cr1.write(|w| w.opm().set_bit().urs().set_bit());
+
+                    let mut opm = OnePulse {
+                        tim,
+                        pins: pin,
+                        clock: clocks.$pclkX().0 * if clocks.$ppreX() == 1 { 1 } else { 2 },
+                        delay: 0,
+                        width: 0,
+                        retriggerable: false,
+                        _channel: PhantomData,
+                    };
+
+                    opm.set_pulse(delay, width).expect("impossible pulse");
+
+                    // OCxM: PWM mode 2, the output is active once CNT >= CCRx
+                    opm.set_output_mode(0b0111);
+
+                    // CCxE: enable the output
+                    let offset = 4 * u32(CHANNEL::INDEX);
+                    opm.tim.ccer.modify(|r, w| unsafe { w.bits(r.bits() | (1 << offset)) });
+
+                    // MOE: main output enable
+                    $(opm.tim.$bdtr.modify(|_, w| w.moe().set_bit());)*
+
+                    opm
+                }
+            }
+
+            impl<CHANNEL, PINS> OnePulse<$TIM, CHANNEL, PINS>
+            where
+                CHANNEL: Channel,
+            {
+                /// Changes the delay and the width of the pulse
+                ///
+                /// The delay goes from the trigger to the start of the pulse and is ignored in
+                /// retriggerable mode. The prescaler is picked to give the finest resolution that
+                /// fits the whole pulse in the counter; the delay is at least one prescaled tick.
+                ///
+                /// This aborts the pulse in progress, if any. On error, the timer configuration is
+                /// left untouched.
+                // NOTE(allow) `w.psc().bits()` is safe for TIM{15,16,17} but not for TIM{2,3,4}
+                // due to some SVD omission
+                #[allow(unused_unsafe)]
+                pub fn set_pulse<D, W>(&mut self, delay: D, width: W) -> Result<(), Error>
+                where
+                    D: Into<MicroSeconds>,
+                    W: Into<MicroSeconds>,
+                {
+                    let (delay, width) = (delay.into().0, width.into().0);
+                    let (psc, ccr, arr) = timings(
+                        self.clock,
+                        delay,
+                        width,
+                        self.retriggerable,
+                        u32($width::max_value()),
+                    )?;
+
+                    self.tim.psc.write(|w| unsafe { w.psc().bits(psc) });
+                    self.tim.arr.write(|w| unsafe { w.bits(arr) });
+                    match CHANNEL::INDEX {
+                        $(
+                            $i => self.tim.$ccrX.write(|w| unsafe { w.bits(ccr) }),
+                        )+
+                        _ => unreachable!(),
+                    }
+
+                    // UG: load the new prescaler value; this also stops the counter
+                    self.tim.egr.write(|w| w.ug().set_bit());
+
+                    self.delay = delay;
+                    self.width = width;
+
+                    Ok(())
+                }
+
+                /// Fires the pulse
+                ///
+                /// This has no effect if a pulse is already in progress
+                pub fn trigger(&mut self) {
+                    self.tim.cr1.modify(|_, w| w.cen().set_bit());
+                }
+
+                /// Returns `true` while a pulse is in progress
+                pub fn is_busy(&self) -> bool {
+                    self.tim.cr1.read().cen().bit_is_set()
+                }
+
+                /// Releases the TIM peripheral and associated pins
+                pub fn free(self) -> ($TIM, PINS) {
+                    // pause counter
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    (self.tim, self.pins)
+                }
+
+                // OCxM[3:0]; bit 3 is not contiguous with the other three
+                fn set_output_mode(&mut self, mode: u32) {
+                    let offset = 8 * u32(CHANNEL::INDEX % 2);
+                    let mask = (0b111 << 4) | (1 << 16);
+                    let bits = ((mode & 0b111) << 4) | ((mode >> 3) << 16);
+
+                    match CHANNEL::INDEX {
+                        $(
+                            $i => self.tim.$ccmrX.modify(|r, w| unsafe {
+                                w.bits((r.bits() & !(mask << offset)) | (bits << offset))
+                            }),
+                        )+
+                        _ => unreachable!(),
+                    }
+                }
+            }
+        )+
+    }
+}
+
+macro_rules! trigger {
+    ($($TIM:ident,)+) => {
+        $(
+            impl<CHANNEL, PINS> OnePulse<$TIM, CHANNEL, PINS>
+            where
+                CHANNEL: Channel,
+            {
+                /// Fires the pulse on every `edge` of the input of channel 1 or 2
+                ///
+                /// Edges that arrive while a pulse is in progress are ignored. `trigger` can still
+                /// be used to fire the pulse from software.
+                pub fn with_trigger<TPIN, TCHANNEL>(
+                    self,
+                    pin: TPIN,
+                    edge: Edge,
+                ) -> OnePulse<$TIM, CHANNEL, (PINS, TPIN)>
+                where
+                    TPIN: CPin<$TIM, TCHANNEL>,
+                    TCHANNEL: TriggerChannel,
+                {
+                    self.triggered::<TPIN, TCHANNEL>(pin, edge, false)
+                }
+
+                /// Restarts the pulse on every `edge` of the input of channel 1 or 2
+                ///
+                /// The pulse starts as soon as the edge is detected, without delay, and is
+                /// extended if another edge arrives before it ends.
+                ///
+                /// # Panics
+                ///
+                /// If the timer can't achieve the pulse; see `set_pulse`
+                pub fn with_retrigger<TPIN, TCHANNEL>(
+                    self,
+                    pin: TPIN,
+                    edge: Edge,
+                ) -> OnePulse<$TIM, CHANNEL, (PINS, TPIN)>
+                where
+                    TPIN: CPin<$TIM, TCHANNEL>,
+                    TCHANNEL: TriggerChannel,
+                {
+                    self.triggered::<TPIN, TCHANNEL>(pin, edge, true)
+                }
+
+                fn triggered<TPIN, TCHANNEL>(
+                    mut self,
+                    pin: TPIN,
+                    edge: Edge,
+                    retriggerable: bool,
+                ) -> OnePulse<$TIM, CHANNEL, (PINS, TPIN)>
+                where
+                    TCHANNEL: TriggerChannel,
+                {
+                    assert!(
+                        TCHANNEL::INDEX != CHANNEL::INDEX,
+                        "the trigger and the pulse must use different channels"
+                    );
+
+                    if retriggerable {
+                        self.retriggerable = true;
+                        let (delay, width) = (MicroSeconds(self.delay), MicroSeconds(self.width));
+                        self.set_pulse(delay, width).expect("impossible pulse");
+
+                        // OCxM: retriggerable OPM mode 2
+                        self.set_output_mode(0b1001);
+                    }
+
+                    // CCyS: ICy is mapped on TIy
+                    let offset = 8 * u32(TCHANNEL::INDEX);
+                    self.tim.ccmr1_output.modify(|r, w| unsafe {
+                        w.bits((r.bits() & !(0b1111_1111 << offset)) | (0b01 << offset))
+                    });
+
+                    // CCyNP:CCyP
+                    let bits = match edge {
+                        Edge::Rising => 0b0000,
+                        Edge::Falling => 0b0010,
+                        Edge::Both => 0b1010,
+                    };
+                    let offset = 4 * u32(TCHANNEL::INDEX);
+                    self.tim.ccer.modify(|r, w| unsafe {
+                        w.bits((r.bits() & !(0b1010 << offset)) | (bits << offset))
+                    });
+
+                    // TS: TI1FP1 or TI2FP2
+                    // SMS: trigger mode or combined reset + trigger mode
+                    let ts = if TCHANNEL::INDEX == 0 { 0b101 } else { 0b110 };
+                    let sms = if retriggerable { 1 << 16 } else { 0b110 };
+                    self.tim.smcr.write(|w| unsafe { w.bits((ts << 4) | sms) });
+
+                    OnePulse {
+                        tim: self.tim,
+                        pins: (self.pins, pin),
+                        clock: self.clock,
+                        delay: self.delay,
+                        width: self.width,
+                        retriggerable: self.retriggerable,
+                        _channel: PhantomData,
+                    }
+                }
+            }
+        )+
+    }
+}
+
+hal! {
+    TIM2: (tim2, APB1, tim2en, tim2rst, pclk1, ppre1, u32, [
+        (0, ccmr1_output, ccr1),
+        (1, ccmr1_output, ccr2),
+        (2, ccmr2_output, ccr3),
+        (3, ccmr2_output, ccr4),
+    ]),
+    TIM3: (tim3, APB1, tim3en, tim3rst, pclk1, ppre1, u16, [
+        (0, ccmr1_output, ccr1),
+        (1, ccmr1_output, ccr2),
+        (2, ccmr2_output, ccr3),
+        (3, ccmr2_output, ccr4),
+    ]),
+    TIM4: (tim4, APB1, tim4en, tim4rst, pclk1, ppre1, u16, [
+        (0, ccmr1_output, ccr1),
+        (1, ccmr1_output, ccr2),
+        (2, ccmr2_output, ccr3),
+        (3, ccmr2_output, ccr4),
+    ]),
+    TIM15: (tim15, APB2, tim15en, tim15rst, pclk2, ppre2, u16, [
+        (0, ccmr1_output, ccr1),
+        (1, ccmr1_output, ccr2),
+    ], bdtr),
+    TIM16: (tim16, APB2, tim16en, tim16rst, pclk2, ppre2, u16, [
+        (0, ccmr1_output, ccr1),
+    ], bdtr),
+    TIM17: (tim17, APB2, tim17en, tim17rst, pclk2, ppre2, u16, [
+        (0, ccmr1_output, ccr1),
+    ], bdtr),
+}
+
+trigger! {
+    TIM2,
+    TIM3,
+    TIM4,
+    TIM15,
+}
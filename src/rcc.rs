@@ -3,6 +3,7 @@
 use core::cmp;
 
 use cast::u32;
+use cortex_m::interrupt;
 use stm32f30x::{rcc, RCC};
 
 use flash::ACR;
@@ -93,7 +94,17 @@ impl APB2 {
     }
 }
 
-const HSI: u32 = 8_000_000; // Hz
+// Modifies the peripheral kernel clock selection, which is shared by several drivers
+pub(crate) fn modify_cfgr3<F>(f: F)
+where
+    for<'w> F: FnOnce(&rcc::cfgr3::R, &'w mut rcc::cfgr3::W) -> &'w mut rcc::cfgr3::W,
+{
+    // NOTE(unsafe) all the modifications are read-modify-writes performed in critical sections
+    interrupt::free(|_| unsafe { (*RCC::ptr()).cfgr3.modify(f) })
+}
+
+pub(crate) const HSI: u32 = 8_000_000; // Hz
+pub(crate) const LSE: u32 = 32_768; // Hz
 
 /// Clock configuration
 pub struct CFGR {
@@ -275,8 +286,6 @@ pub struct Clocks {
     pclk1: Hertz,
     pclk2: Hertz,
     ppre1: u8,
-    // TODO remove `allow`
-    #[allow(dead_code)]
     ppre2: u8,
     sysclk: Hertz,
 }
@@ -301,8 +310,6 @@ impl Clocks {
         self.ppre1
     }
 
-    // TODO remove `allow`
-    #[allow(dead_code)]
     pub(crate) fn ppre2(&self) -> u8 {
         self.ppre2
     }
@@ -1,21 +1,30 @@
 //! Serial
 
+use core::fmt;
 use core::marker::PhantomData;
-use core::ptr;
+use core::sync::atomic::{self, AtomicPtr, AtomicUsize, Ordering};
+use core::{ptr, slice};
 
+use cast::u32;
+use cortex_m::interrupt;
+use hal::blocking;
 use hal::serial;
+use hal::spi::{FullDuplex, Mode, Phase, Polarity};
 use nb;
-use stm32f30x::{USART1, USART2, USART3};
+use stm32f30x::{RCC, UART4, UART5, USART1, USART2, USART3};
 use void::Void;
 
-use gpio::gpioa::{PA10, PA2, PA3, PA9};
-use gpio::gpiob::{PB10, PB11, PB6, PB7};
-use gpio::gpioc::{PC10, PC11, PC4, PC5};
-use gpio::gpiod::{PD5, PD6, PD8, PD9};
+use dma::{self, dma1, dma2, CircBuffer, RxDma, Transfer, TransferPayload, TxDma, R, W};
+
+use gpio::gpioa::{PA0, PA1, PA10, PA11, PA12, PA2, PA3, PA4, PA8, PA9};
+use gpio::gpiob::{PB10, PB11, PB12, PB13, PB14, PB5, PB6, PB7};
+use gpio::gpioc::{PC10, PC11, PC12, PC4, PC5};
+use gpio::gpiod::{PD10, PD11, PD12, PD2, PD3, PD4, PD5, PD6, PD7, PD8, PD9};
 use gpio::gpioe::{PE0, PE1, PE15};
-use gpio::AF7;
-use rcc::{APB1, APB2, Clocks};
-use time::Bps;
+use gpio::gpiof::PF6;
+use gpio::{AF5, AF7};
+use rcc::{self, APB1, APB2, Clocks};
+use time::{Bps, Hertz};
 
 /// Interrupt event
 pub enum Event {
@@ -23,6 +32,16 @@ pub enum Event {
     Rxne,
     /// New data can be sent
     Txe,
+    /// The line has been idle for one frame after receiving data
+    Idle,
+    /// The match character has been received; see `Serial::set_match_character`
+    CharacterMatch,
+    /// A break has been detected; only in LIN mode
+    LinBreak,
+    /// The wake-up event has occurred; see `Serial::enable_wake_up`
+    WakeUp,
+    /// The line has been idle for the receiver timeout; see `Serial::set_receiver_timeout`
+    ReceiverTimeout,
 }
 
 /// Serial error
@@ -36,10 +55,321 @@ pub enum Error {
     Overrun,
     /// Parity check error
     Parity,
+    /// Automatic baud rate detection failed
+    AutoBaudRate,
+    /// SmartCard mode: the card rejected a transmitted character after all the retries
+    Nack,
+    /// The line has been idle for the receiver timeout; see `Serial::set_receiver_timeout`
+    ReceiverTimeout,
     #[doc(hidden)]
     _Extensible,
 }
 
+/// IrDA SIR mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IrdaMode {
+    /// Normal mode; the pulses are 3/16 of a bit time wide
+    Normal,
+    /// Low power mode; the pulses are 3 periods of a ~1.8432 MHz clock wide
+    LowPower,
+}
+
+/// Kernel clock that keeps the USART running in Stop mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopModeClock {
+    /// The 32.768 kHz external oscillator, which must be already running
+    Lse = 0b10,
+    /// The 8 MHz internal oscillator, which the USART turns on when needed
+    Hsi = 0b11,
+}
+
+/// Event that wakes the MCU up from Stop mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WakeUpSource {
+    /// The start bit of a frame has been detected
+    StartBit = 0b10,
+    /// A whole frame has been received
+    Rxne = 0b11,
+}
+
+/// Stop mode configuration of a USART that wakes the MCU up
+///
+/// The default is the HSI kernel clock and the `StartBit` wake-up source.
+#[derive(Clone, Copy, Debug)]
+pub struct StopMode {
+    clock: StopModeClock,
+    source: WakeUpSource,
+}
+
+impl StopMode {
+    /// Sets the kernel clock
+    pub fn clock(mut self, clock: StopModeClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets the wake-up event source
+    pub fn source(mut self, source: WakeUpSource) -> Self {
+        self.source = source;
+        self
+    }
+}
+
+impl Default for StopMode {
+    fn default() -> Self {
+        StopMode {
+            clock: StopModeClock::Hsi,
+            source: WakeUpSource::StartBit,
+        }
+    }
+}
+
+/// Clock pulse of the last data bit in synchronous mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LastBitClock {
+    /// The clock pulse of the last data bit is output on CK, as expected by SPI devices
+    Output,
+    /// The clock pulse of the last data bit is not output on CK
+    Suppress,
+}
+
+/// Automatic baud rate detection mode, i.e. what the first received frame looks like
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AutoBaudRateMode {
+    /// Any character starting with a 1 bit; the start bit is measured
+    StartBit = 0b00,
+    /// Any character starting with the 10xx bit pattern; the falling edge to falling edge time is
+    /// measured
+    FallingEdge = 0b01,
+    /// The 0x7F character
+    Frame0x7F = 0b10,
+    /// The 0x55 character
+    Frame0x55 = 0b11,
+}
+
+/// Parity bit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Parity {
+    /// No parity bit
+    None,
+    /// Even parity
+    Even,
+    /// Odd parity
+    Odd,
+}
+
+/// Number of stop bits
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopBits {
+    /// 1 stop bit
+    Stop1 = 0b00,
+    /// 0.5 stop bits
+    Stop0p5 = 0b01,
+    /// 2 stop bits
+    Stop2 = 0b10,
+    /// 1.5 stop bits
+    Stop1p5 = 0b11,
+}
+
+/// Number of data bits, not counting the parity bit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WordLength {
+    /// 7 data bits
+    DataBits7 = 7,
+    /// 8 data bits
+    DataBits8 = 8,
+    /// 9 data bits; use the `u16` versions of `Read` and `Write`
+    DataBits9 = 9,
+}
+
+/// Receiver oversampling
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Oversampling {
+    /// Oversampling by 8; doubles the maximum baud rate but is less tolerant to clock deviations
+    Over8,
+    /// Oversampling by 16
+    Over16,
+}
+
+/// Polarity of the RS-485 driver enable signal
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DePolarity {
+    /// DE is high while transmitting
+    ActiveHigh,
+    /// DE is low while transmitting
+    ActiveLow,
+}
+
+/// RS-485 driver enable configuration
+///
+/// The assertion time goes from the activation of DE to the start bit of the first transmitted
+/// frame; the deassertion time goes from the end of the last stop bit to the deactivation of DE.
+/// Both are expressed in sixteenths of a bit time. The hardware counts them in sample times, up to
+/// 31: sixteenths of a bit time with `Oversampling::Over16` and eighths of a bit time with
+/// `Oversampling::Over8`, in which case odd values are rounded up to the next eighth. The default
+/// is an active high DE with no assertion or deassertion time.
+#[derive(Clone, Copy, Debug)]
+pub struct DriverEnable {
+    polarity: DePolarity,
+    assertion_time: u8,
+    deassertion_time: u8,
+}
+
+impl DriverEnable {
+    /// Sets the polarity of DE
+    pub fn polarity(mut self, polarity: DePolarity) -> Self {
+        self.polarity = polarity;
+        self
+    }
+
+    /// Sets the assertion time, in sixteenths of a bit time
+    pub fn assertion_time(mut self, sixteenths: u8) -> Self {
+        self.assertion_time = sixteenths;
+        self
+    }
+
+    /// Sets the deassertion time, in sixteenths of a bit time
+    pub fn deassertion_time(mut self, sixteenths: u8) -> Self {
+        self.deassertion_time = sixteenths;
+        self
+    }
+
+    // DEAT and DEDT fields, in sample times
+    fn times(&self, oversampling: Oversampling) -> (u8, u8) {
+        let to_samples = |sixteenths: u8| {
+            let samples = match oversampling {
+                Oversampling::Over16 => sixteenths,
+                // NOTE odd values lie halfway between two eighths; rounding up never shortens
+                // the requested time
+                Oversampling::Over8 => sixteenths / 2 + sixteenths % 2,
+            };
+            assert!(samples < 32, "driver enable time too long");
+            samples
+        };
+
+        (
+            to_samples(self.assertion_time),
+            to_samples(self.deassertion_time),
+        )
+    }
+}
+
+impl Default for DriverEnable {
+    fn default() -> Self {
+        DriverEnable {
+            polarity: DePolarity::ActiveHigh,
+            assertion_time: 0,
+            deassertion_time: 0,
+        }
+    }
+}
+
+/// Serial configuration
+///
+/// The default configuration is 115200 bps 8N1 with oversampling by 16. A `Bps` value converts
+/// into the default configuration at that baud rate.
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    baud_rate: u32,
+    parity: Parity,
+    stop_bits: StopBits,
+    word_length: WordLength,
+    oversampling: Oversampling,
+}
+
+impl Config {
+    /// Sets the baud rate
+    pub fn baud_rate(mut self, baud_rate: Bps) -> Self {
+        self.baud_rate = baud_rate.0;
+        self
+    }
+
+    /// Sets the parity bit
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    /// Sets the number of stop bits
+    pub fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    /// Sets the number of data bits
+    pub fn word_length(mut self, word_length: WordLength) -> Self {
+        self.word_length = word_length;
+        self
+    }
+
+    /// Sets the receiver oversampling
+    pub fn oversampling(mut self, oversampling: Oversampling) -> Self {
+        self.oversampling = oversampling;
+        self
+    }
+
+    // BRR value for a USART clocked at `fck`
+    fn brr(&self, fck: u32) -> u32 {
+        match self.oversampling {
+            Oversampling::Over16 => {
+                let usartdiv = fck / self.baud_rate;
+                assert!(usartdiv >= 16, "impossible baud rate");
+                usartdiv
+            }
+            Oversampling::Over8 => {
+                let usartdiv = 2 * fck / self.baud_rate;
+                assert!(usartdiv >= 16, "impossible baud rate");
+                // NOTE BRR[3] must be kept cleared and BRR[2:0] = USARTDIV[3:0] >> 1
+                (usartdiv & !0xf) | ((usartdiv & 0xf) >> 1)
+            }
+        }
+    }
+
+    // M1 and M0 bits of CR1
+    fn m_bits(&self) -> u32 {
+        let parity = if self.parity == Parity::None { 0 } else { 1 };
+        match self.word_length as u32 + parity {
+            7 => 1 << 28,
+            8 => 0,
+            9 => 1 << 12,
+            _ => panic!("unsupported frame format"),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            baud_rate: 115_200,
+            parity: Parity::None,
+            stop_bits: StopBits::Stop1,
+            word_length: WordLength::DataBits8,
+            oversampling: Oversampling::Over16,
+        }
+    }
+}
+
+impl Into<Config> for Bps {
+    fn into(self) -> Config {
+        Config::default().baud_rate(self)
+    }
+}
+
+// Data bits of the received frames given the CR1 configuration; the MSB of RDR holds the parity
+// bit when parity is enabled
+fn data_mask(cr1: u32) -> u16 {
+    let frame = if cr1 & (1 << 28) != 0 {
+        7
+    } else if cr1 & (1 << 12) != 0 {
+        9
+    } else {
+        8
+    };
+    let data = if cr1 & (1 << 10) != 0 { frame - 1 } else { frame };
+
+    (1 << data) - 1
+}
+
 // FIXME these should be "closed" traits
 /// TX pin - DO NOT IMPLEMENT THIS TRAIT
 pub unsafe trait TxPin<USART> {}
@@ -47,6 +377,15 @@ pub unsafe trait TxPin<USART> {}
 /// RX pin - DO NOT IMPLEMENT THIS TRAIT
 pub unsafe trait RxPin<USART> {}
 
+/// RTS pin - DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait RtsPin<USART> {}
+
+/// CTS pin - DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait CtsPin<USART> {}
+
+/// CK pin - DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait CkPin<USART> {}
+
 unsafe impl TxPin<USART1> for PA9<AF7> {}
 unsafe impl TxPin<USART1> for PB6<AF7> {}
 unsafe impl TxPin<USART1> for PC4<AF7> {}
@@ -76,6 +415,42 @@ unsafe impl RxPin<USART3> for PC11<AF7> {}
 unsafe impl RxPin<USART3> for PD9<AF7> {}
 unsafe impl RxPin<USART3> for PE15<AF7> {}
 
+unsafe impl TxPin<UART4> for PC10<AF5> {}
+
+unsafe impl RxPin<UART4> for PC11<AF5> {}
+
+unsafe impl TxPin<UART5> for PC12<AF5> {}
+
+unsafe impl RxPin<UART5> for PD2<AF5> {}
+
+unsafe impl RtsPin<USART1> for PA12<AF7> {}
+
+unsafe impl CtsPin<USART1> for PA11<AF7> {}
+
+unsafe impl RtsPin<USART2> for PA1<AF7> {}
+unsafe impl RtsPin<USART2> for PD4<AF7> {}
+
+unsafe impl CtsPin<USART2> for PA0<AF7> {}
+unsafe impl CtsPin<USART2> for PD3<AF7> {}
+
+unsafe impl RtsPin<USART3> for PB14<AF7> {}
+unsafe impl RtsPin<USART3> for PD12<AF7> {}
+unsafe impl RtsPin<USART3> for PF6<AF7> {}
+
+// unsafe impl CtsPin<USART3> for PA13<AF7> {}
+unsafe impl CtsPin<USART3> for PB13<AF7> {}
+unsafe impl CtsPin<USART3> for PD11<AF7> {}
+
+unsafe impl CkPin<USART1> for PA8<AF7> {}
+
+unsafe impl CkPin<USART2> for PA4<AF7> {}
+unsafe impl CkPin<USART2> for PB5<AF7> {}
+unsafe impl CkPin<USART2> for PD7<AF7> {}
+
+unsafe impl CkPin<USART3> for PB12<AF7> {}
+unsafe impl CkPin<USART3> for PC12<AF7> {}
+unsafe impl CkPin<USART3> for PD10<AF7> {}
+
 /// Serial abstraction
 pub struct Serial<USART, PINS> {
     usart: USART,
@@ -92,47 +467,215 @@ pub struct Tx<USART> {
     _usart: PhantomData<USART>,
 }
 
+/// USART peripheral operating as a full duplex SPI master
+///
+/// See `SyncSerial::usartX`
+pub struct SyncSerial<USART, PINS> {
+    usart: USART,
+    pins: PINS,
+}
+
+/// Interrupt driven serial port that buffers data in ring buffers
+///
+/// See `Serial::buffered`
+pub struct BufferedSerial<USART> {
+    tx: Tx<USART>,
+    rx: Rx<USART>,
+}
+
+// Lock-free byte queue with a single producer and a single consumer; one side is the USART
+// interrupt handler and the other side is the `BufferedSerial`
+struct Ring {
+    buffer: AtomicPtr<u8>,
+    // NOTE one slot is always kept free to tell a full queue from an empty one
+    len: AtomicUsize,
+    // next slot to read; only written by the consumer
+    head: AtomicUsize,
+    // next slot to write; only written by the producer
+    tail: AtomicUsize,
+    overflows: AtomicUsize,
+}
+
+const RING: Ring = Ring {
+    buffer: AtomicPtr::new(ptr::null_mut()),
+    len: AtomicUsize::new(0),
+    head: AtomicUsize::new(0),
+    tail: AtomicUsize::new(0),
+    overflows: AtomicUsize::new(0),
+};
+
+impl Ring {
+    fn init(&self, buffer: &'static mut [u8]) {
+        assert!(buffer.len() > 1, "ring buffer too small");
+
+        self.len.store(0, Ordering::SeqCst);
+        self.head.store(0, Ordering::SeqCst);
+        self.tail.store(0, Ordering::SeqCst);
+        self.overflows.store(0, Ordering::SeqCst);
+        self.buffer.store(buffer.as_mut_ptr(), Ordering::SeqCst);
+        self.len.store(buffer.len(), Ordering::SeqCst);
+    }
+
+    // Returns the buffer given to `init`
+    fn deinit(&self) -> &'static mut [u8] {
+        let len = self.len.swap(0, Ordering::SeqCst);
+        let buffer = self.buffer.swap(ptr::null_mut(), Ordering::SeqCst);
+
+        // NOTE(unsafe) `init` took ownership of the buffer and with `len = 0` neither the producer
+        // nor the consumer access it anymore
+        unsafe { slice::from_raw_parts_mut(buffer, len) }
+    }
+
+    // Producer side; returns `false` if the queue is full
+    fn enqueue(&self, byte: u8) -> bool {
+        let len = self.len.load(Ordering::Relaxed);
+        if len == 0 {
+            return false;
+        }
+
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % len;
+        if next == self.head.load(Ordering::Acquire) {
+            return false;
+        }
+
+        // NOTE(unsafe) the consumer doesn't access the slots in between `tail` and `head`
+        unsafe {
+            *self.buffer.load(Ordering::Relaxed).offset(tail as isize) = byte;
+        }
+        self.tail.store(next, Ordering::Release);
+
+        true
+    }
+
+    // Consumer side
+    fn dequeue(&self) -> Option<u8> {
+        let len = self.len.load(Ordering::Relaxed);
+        if len == 0 {
+            return None;
+        }
+
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+
+        // NOTE(unsafe) the producer doesn't access the slots in between `head` and `tail`
+        let byte = unsafe { *self.buffer.load(Ordering::Relaxed).offset(head as isize) };
+        self.head.store((head + 1) % len, Ordering::Release);
+
+        Some(byte)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+}
+
+// NOTE the SVD lacks the UART4EN and UART5EN fields; their bit position is used instead
+macro_rules! enable {
+    ($apb:expr, $usartXen:ident) => {
+        $apb.enr().modify(|_, w| w.$usartXen().enabled())
+    };
+    ($apb:expr, $bit:expr) => {
+        $apb.enr().modify(|r, w| unsafe { w.bits(r.bits() | (1 << $bit)) })
+    };
+}
+
 macro_rules! hal {
     ($(
-        $USARTX:ident: ($usartX:ident, $APB:ident, $usartXen:ident, $usartXrst:ident, $pclkX:ident),
+        $USARTX:ident: (
+            $usartX:ident,
+            $APB:ident,
+            $usartXen:tt,
+            $usartXrst:ident,
+            $usartXsw:ident,
+            $pclkX:ident
+        ),
     )+) => {
         $(
             impl<TX, RX> Serial<$USARTX, (TX, RX)> {
                 /// Configures a USART peripheral to provide serial communication
-                pub fn $usartX(
+                ///
+                /// # Panics
+                ///
+                /// If the baud rate can't be achieved or the frame (data and parity bits) is not
+                /// 7, 8 or 9 bits long
+                pub fn $usartX<C>(
                     usart: $USARTX,
                     pins: (TX, RX),
-                    baud_rate: Bps,
+                    config: C,
                     clocks: Clocks,
                     apb: &mut $APB,
                 ) -> Self
                 where
+                    C: Into<Config>,
                     TX: TxPin<$USARTX>,
                     RX: RxPin<$USARTX>,
                 {
+                    Self::configure(usart, pins, config.into(), clocks, apb)
+                }
+            }
+
+            impl<PINS> Serial<$USARTX, PINS> {
+                // Enables the USART and configures it in standard (asynchronous) mode
+                fn configure(
+                    usart: $USARTX,
+                    pins: PINS,
+                    config: Config,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Self {
                     // enable or reset $USARTX
-                    apb.enr().modify(|_, w| w.$usartXen().enabled());
+                    enable!(apb, $usartXen);
                     apb.rstr().modify(|_, w| w.$usartXrst().set_bit());
                     apb.rstr().modify(|_, w| w.$usartXrst().clear_bit());
 
-                    // disable hardware flow control
-                    // TODO enable DMA
-                    // usart.cr3.write(|w| w.rtse().clear_bit().ctse().clear_bit());
+                    // kernel clock: PCLK; the RCC reset doesn't restore it
+                    rcc::modify_cfgr3(|_, w| unsafe { w.$usartXsw().bits(0b00) });
 
-                    let brr = clocks.$pclkX().0 / baud_rate.0;
-                    assert!(brr >= 16, "impossible baud rate");
+                    let brr = config.brr(clocks.$pclkX().0);
                     usart.brr.write(|w| unsafe { w.bits(brr) });
 
+                    usart
+                        .cr2
+                        .write(|w| unsafe { w.stop().bits(config.stop_bits as u8) });
+
+                    // M1, M0: word length
+                    // OVER8: oversampling
+                    // PCE, PS: parity
                     // UE: enable USART
                     // RE: enable receiver
                     // TE: enable transceiver
-                    usart
-                        .cr1
-                        .write(|w| w.ue().set_bit().re().set_bit().te().set_bit());
+                    usart.cr1.write(|w| unsafe {
+                        w.bits(config.m_bits())
+                            .over8()
+                            .bit(config.oversampling == Oversampling::Over8)
+                            .pce()
+                            .bit(config.parity != Parity::None)
+                            .ps()
+                            .bit(config.parity == Parity::Odd)
+                            .ue()
+                            .set_bit()
+                            .re()
+                            .set_bit()
+                            .te()
+                            .set_bit()
+                    });
 
                     Serial { usart, pins }
                 }
 
+                // Runs `f` with the USART disabled, as required to change most of its settings
+                fn reconfigure<F>(&mut self, f: F)
+                where
+                    F: FnOnce(&$USARTX),
+                {
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    f(&self.usart);
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+
                 /// Starts listening for an interrupt event
                 pub fn listen(&mut self, event: Event) {
                     match event {
@@ -142,6 +685,21 @@ macro_rules! hal {
                         Event::Txe => {
                             self.usart.cr1.modify(|_, w| w.txeie().set_bit())
                         },
+                        Event::Idle => {
+                            self.usart.cr1.modify(|_, w| w.idleie().set_bit())
+                        },
+                        Event::CharacterMatch => {
+                            self.usart.cr1.modify(|_, w| w.cmie().set_bit())
+                        },
+                        Event::LinBreak => {
+                            self.usart.cr2.modify(|_, w| w.lbdie().set_bit())
+                        },
+                        Event::WakeUp => {
+                            self.usart.cr3.modify(|_, w| w.wufie().set_bit())
+                        },
+                        Event::ReceiverTimeout => {
+                            self.usart.cr1.modify(|_, w| w.rtoie().set_bit())
+                        },
                     }
                 }
 
@@ -154,9 +712,89 @@ macro_rules! hal {
                         Event::Txe => {
                             self.usart.cr1.modify(|_, w| w.txeie().clear_bit())
                         },
+                        Event::Idle => {
+                            self.usart.cr1.modify(|_, w| w.idleie().clear_bit())
+                        },
+                        Event::CharacterMatch => {
+                            self.usart.cr1.modify(|_, w| w.cmie().clear_bit())
+                        },
+                        Event::LinBreak => {
+                            self.usart.cr2.modify(|_, w| w.lbdie().clear_bit())
+                        },
+                        Event::WakeUp => {
+                            self.usart.cr3.modify(|_, w| w.wufie().clear_bit())
+                        },
+                        Event::ReceiverTimeout => {
+                            self.usart.cr1.modify(|_, w| w.rtoie().clear_bit())
+                        },
                     }
                 }
 
+                /// Sets the character that raises the `CharacterMatch` event when received
+                pub fn set_match_character(&mut self, character: u8) {
+                    // NOTE ADD can only be written while the receiver is disabled
+                    let re = self.usart.cr1.read().re().bit();
+                    self.usart.cr1.modify(|_, w| w.re().clear_bit());
+                    self.usart.cr2.modify(|r, w| unsafe {
+                        w.bits((r.bits() & 0x00ff_ffff) | (u32(character) << 24))
+                    });
+                    self.usart.cr1.modify(|_, w| w.re().bit(re));
+                }
+
+                /// Starts the receiver timeout, which expires when the line has been idle for
+                /// `bit_times` bit durations after the last received frame
+                ///
+                /// For example, the 3.5 character gap that ends a Modbus RTU frame is 39 bit
+                /// times with 11-bit frames. The expiration is reported by the `ReceiverTimeout`
+                /// event and as an `Error::ReceiverTimeout` by `read`.
+                ///
+                /// # Panics
+                ///
+                /// If `bit_times` doesn't fit in 24 bits
+                pub fn set_receiver_timeout(&mut self, bit_times: u32) {
+                    assert!(bit_times < 1 << 24, "receiver timeout too long");
+
+                    // RTO: receiver timeout value
+                    self.usart.rtor.modify(|_, w| unsafe { w.rto().bits(bit_times) });
+                    // RTOEN: receiver timeout enabled
+                    self.usart.cr2.modify(|_, w| w.rtoen().set_bit());
+                }
+
+                /// Stops the receiver timeout
+                pub fn disable_receiver_timeout(&mut self) {
+                    self.usart.cr2.modify(|_, w| w.rtoen().clear_bit());
+                }
+
+                /// Sends a break (a whole frame of zeros) after the frame in progress
+                pub fn send_break(&mut self) {
+                    self.usart.rqr.write(|w| w.sbkrq().set_bit());
+                }
+
+                /// Turns the `Serial` abstraction into an interrupt driven serial port
+                ///
+                /// The received data is buffered in `rx_buffer` and the data to send in
+                /// `tx_buffer`. `BufferedSerial::on_interrupt` must be called from the interrupt
+                /// handler of this USART.
+                ///
+                /// # Panics
+                ///
+                /// If a buffer is shorter than 2 bytes
+                pub fn buffered(
+                    self,
+                    rx_buffer: &'static mut [u8],
+                    tx_buffer: &'static mut [u8],
+                ) -> BufferedSerial<$USARTX> {
+                    let (rx_ring, tx_ring) = BufferedSerial::<$USARTX>::rings();
+                    rx_ring.init(rx_buffer);
+                    tx_ring.init(tx_buffer);
+
+                    // RXNEIE: the interrupt handler takes the received data
+                    self.usart.cr1.modify(|_, w| w.rxneie().set_bit());
+
+                    let (tx, rx) = self.split();
+                    BufferedSerial { tx, rx }
+                }
+
                 /// Splits the `Serial` abstraction into a transmitter and a receiver half
                 pub fn split(self) -> (Tx<$USARTX>, Rx<$USARTX>) {
                     (
@@ -170,7 +808,7 @@ macro_rules! hal {
                 }
 
                 /// Releases the USART peripheral and associated pins
-                pub fn free(self) -> ($USARTX, (TX, RX)) {
+                pub fn free(self) -> ($USARTX, PINS) {
                     (self.usart, self.pins)
                 }
             }
@@ -179,22 +817,54 @@ macro_rules! hal {
                 type Error = Error;
 
                 fn read(&mut self) -> nb::Result<u8, Error> {
+                    serial::Read::<u16>::read(self).map(|word| word as u8)
+                }
+            }
+
+            impl serial::Read<u16> for Rx<$USARTX> {
+                type Error = Error;
+
+                // NOTE reporting an error clears its flag; the received word, if any, is kept and
+                // returned by the next read
+                fn read(&mut self) -> nb::Result<u16, Error> {
                     // NOTE(unsafe) atomic read with no side effects
                     let isr = unsafe { (*$USARTX::ptr()).isr.read() };
+                    // NOTE(unsafe) write-to-clear register with no side effects on other flags
+                    let icr = unsafe { &(*$USARTX::ptr()).icr };
 
                     Err(if isr.pe().bit_is_set() {
+                        icr.write(|w| w.pecf().set_bit());
                         nb::Error::Other(Error::Parity)
                     } else if isr.fe().bit_is_set() {
-                        nb::Error::Other(Error::Framing)
+                        icr.write(|w| w.fecf().set_bit());
+
+                        // NOTE(unsafe) atomic read with no side effects
+                        let cr3 = unsafe { (*$USARTX::ptr()).cr3.read() };
+                        if cr3.scen().bit_is_set() {
+                            nb::Error::Other(Error::Nack)
+                        } else {
+                            nb::Error::Other(Error::Framing)
+                        }
                     } else if isr.nf().bit_is_set() {
+                        icr.write(|w| w.ncf().set_bit());
                         nb::Error::Other(Error::Noise)
                     } else if isr.ore().bit_is_set() {
+                        icr.write(|w| w.orecf().set_bit());
                         nb::Error::Other(Error::Overrun)
                     } else if isr.rxne().bit_is_set() {
+                        // NOTE(unsafe) atomic read with no side effects
+                        let cr1 = unsafe { (*$USARTX::ptr()).cr1.read().bits() };
+
                         // NOTE(read_volatile) see `write_volatile` below
-                        return Ok(unsafe {
+                        let word: u16 = unsafe {
                             ptr::read_volatile(&(*$USARTX::ptr()).rdr as *const _ as *const _)
-                        });
+                        };
+
+                        return Ok(word & data_mask(cr1));
+                    } else if isr.rtof().bit_is_set() {
+                        // NOTE checked after RXNE so that the last received word is not lost
+                        icr.write(|w| w.rtocf().set_bit());
+                        nb::Error::Other(Error::ReceiverTimeout)
                     } else {
                         nb::Error::WouldBlock
                     })
@@ -203,11 +873,13 @@ macro_rules! hal {
 
             impl serial::Write<u8> for Tx<$USARTX> {
                 // NOTE(Void) See section "29.7 USART interrupts"; the only possible errors during
-                // transmission are: clear to send (which is disabled in this case) errors and
-                // framing errors (which only occur in SmartCard mode); neither of these apply to
-                // our hardware configuration
+                // transmission are: clear to send errors (which don't apply as the transmitter just
+                // waits for CTS) and framing errors (which only occur in SmartCard mode, where they
+                // are reported by the receiver as `Error::Nack`)
                 type Error = Void;
 
+                // NOTE completes once TC is set, i.e. when the last frame has been completely
+                // shifted out; not just when TDR is empty
                 fn flush(&mut self) -> nb::Result<(), Void> {
                     // NOTE(unsafe) atomic read with no side effects
                     let isr = unsafe { (*$USARTX::ptr()).isr.read() };
@@ -235,12 +907,1100 @@ macro_rules! hal {
                     }
                 }
             }
-        )+
-    }
-}
 
-hal! {
-    USART1: (usart1, APB2, usart1en, usart1rst, pclk2),
-    USART2: (usart2, APB1, usart2en, usart2rst, pclk1),
-    USART3: (usart3, APB1, usart3en, usart3rst, pclk1),
+            impl serial::Write<u16> for Tx<$USARTX> {
+                // NOTE(Void) see `Write<u8>`
+                type Error = Void;
+
+                fn flush(&mut self) -> nb::Result<(), Void> {
+                    serial::Write::<u8>::flush(self)
+                }
+
+                fn write(&mut self, word: u16) -> nb::Result<(), Void> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    let isr = unsafe { (*$USARTX::ptr()).isr.read() };
+
+                    if isr.txe().bit_is_set() {
+                        // NOTE(unsafe) atomic write to stateless register
+                        // NOTE(write_volatile) 16-bit write that's not possible through the
+                        // svd2rust API
+                        unsafe {
+                            ptr::write_volatile(&(*$USARTX::ptr()).tdr as *const _ as *mut _, word)
+                        }
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+            }
+
+            impl Tx<$USARTX> {
+                /// Sends a break (a whole frame of zeros) after the frame in progress
+                pub fn send_break(&mut self) {
+                    // NOTE(unsafe) atomic write to a request register owned by the transmitter
+                    unsafe { (*$USARTX::ptr()).rqr.write(|w| w.sbkrq().set_bit()) }
+                }
+            }
+
+            impl blocking::serial::write::Default<u8> for Tx<$USARTX> {}
+
+            impl fmt::Write for Tx<$USARTX> {
+                fn write_str(&mut self, s: &str) -> fmt::Result {
+                    use hal::blocking::serial::Write;
+
+                    self.bwrite_all(s.as_bytes()).map_err(|_| fmt::Error)
+                }
+            }
+
+            impl Rx<$USARTX> {
+                /// Returns `true` if an idle line has been detected
+                pub fn is_idle(&self) -> bool {
+                    // NOTE(unsafe) atomic read with no side effects
+                    unsafe { (*$USARTX::ptr()).isr.read().idle().bit_is_set() }
+                }
+
+                /// Clears the idle line flag
+                pub fn clear_idle(&mut self) {
+                    // NOTE(unsafe) write-to-clear register with no side effects on other flags
+                    unsafe { (*$USARTX::ptr()).icr.write(|w| w.idlecf().set_bit()) }
+                }
+
+                /// Returns `true` if the match character has been received
+                pub fn is_character_match(&self) -> bool {
+                    // NOTE(unsafe) atomic read with no side effects
+                    unsafe { (*$USARTX::ptr()).isr.read().cmf().bit_is_set() }
+                }
+
+                /// Clears the character match flag
+                pub fn clear_character_match(&mut self) {
+                    // NOTE(unsafe) write-to-clear register with no side effects on other flags
+                    unsafe { (*$USARTX::ptr()).icr.write(|w| w.cmcf().set_bit()) }
+                }
+
+                /// Returns `true` if a LIN break has been detected
+                pub fn is_lin_break(&self) -> bool {
+                    // NOTE(unsafe) atomic read with no side effects
+                    unsafe { (*$USARTX::ptr()).isr.read().lbdf().bit_is_set() }
+                }
+
+                /// Clears the LIN break flag
+                pub fn clear_lin_break(&mut self) {
+                    // NOTE(unsafe) write-to-clear register with no side effects on other flags
+                    unsafe { (*$USARTX::ptr()).icr.write(|w| w.lbdcf().set_bit()) }
+                }
+
+                /// Returns `true` if the receiver timeout has expired
+                pub fn is_receiver_timeout(&self) -> bool {
+                    // NOTE(unsafe) atomic read with no side effects
+                    unsafe { (*$USARTX::ptr()).isr.read().rtof().bit_is_set() }
+                }
+
+                /// Clears the receiver timeout flag
+                pub fn clear_receiver_timeout(&mut self) {
+                    // NOTE(unsafe) write-to-clear register with no side effects on other flags
+                    unsafe { (*$USARTX::ptr()).icr.write(|w| w.rtocf().set_bit()) }
+                }
+
+                /// Returns `true` if the wake-up event has occurred
+                pub fn is_wake_up(&self) -> bool {
+                    // NOTE(unsafe) atomic read with no side effects
+                    unsafe { (*$USARTX::ptr()).isr.read().wuf().bit_is_set() }
+                }
+
+                /// Clears the wake-up flag
+                pub fn clear_wake_up(&mut self) {
+                    // NOTE(unsafe) write-to-clear register with no side effects on other flags
+                    unsafe { (*$USARTX::ptr()).icr.write(|w| w.wucf().set_bit()) }
+                }
+
+                /// Clears all the pending reception errors
+                pub fn clear_errors(&mut self) {
+                    // NOTE(unsafe) write-to-clear register with no side effects on other flags
+                    unsafe {
+                        (*$USARTX::ptr()).icr.write(|w| {
+                            w.pecf()
+                                .set_bit()
+                                .fecf()
+                                .set_bit()
+                                .ncf()
+                                .set_bit()
+                                .orecf()
+                                .set_bit()
+                        })
+                    }
+                }
+            }
+
+            impl BufferedSerial<$USARTX> {
+                fn rings() -> (&'static Ring, &'static Ring) {
+                    static RX: Ring = RING;
+                    static TX: Ring = RING;
+
+                    (&RX, &TX)
+                }
+
+                /// Moves data between the USART and the ring buffers
+                ///
+                /// This must be called from the interrupt handler of this USART while the
+                /// `BufferedSerial` is in use.
+                pub fn on_interrupt() {
+                    // NOTE(unsafe) the other users of these registers only perform atomic reads
+                    // and writes, or read-modify-writes in critical sections
+                    let usart = unsafe { &*$USARTX::ptr() };
+                    let (rx_ring, tx_ring) = Self::rings();
+
+                    let isr = usart.isr.read();
+
+                    if isr.ore().bit_is_set() {
+                        // the received data didn't make it into the ring buffer
+                        usart.icr.write(|w| w.orecf().set_bit());
+                        rx_ring.overflows.fetch_add(1, Ordering::Relaxed);
+                    }
+
+                    if isr.pe().bit_is_set() || isr.fe().bit_is_set() || isr.nf().bit_is_set() {
+                        usart
+                            .icr
+                            .write(|w| w.pecf().set_bit().fecf().set_bit().ncf().set_bit());
+                    }
+
+                    if isr.rxne().bit_is_set() {
+                        // NOTE(read_volatile) see `Read`
+                        let byte: u8 = unsafe {
+                            ptr::read_volatile(&usart.rdr as *const _ as *const _)
+                        };
+
+                        if !rx_ring.enqueue(byte) {
+                            rx_ring.overflows.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+
+                    if isr.txe().bit_is_set() && usart.cr1.read().txeie().bit_is_set() {
+                        match tx_ring.dequeue() {
+                            Some(byte) => unsafe {
+                                // NOTE(write_volatile) see `Write`
+                                ptr::write_volatile(&usart.tdr as *const _ as *mut _, byte)
+                            },
+                            // nothing else to send
+                            None => usart.cr1.modify(|_, w| w.txeie().clear_bit()),
+                        }
+                    }
+                }
+
+                /// Returns the number of received bytes that were lost because the RX buffer was
+                /// full or because of an overrun
+                pub fn rx_overflows(&self) -> usize {
+                    Self::rings().0.overflows.load(Ordering::Relaxed)
+                }
+
+                /// Returns the number of bytes that `fmt::Write` dropped because the TX buffer was
+                /// full
+                pub fn tx_overflows(&self) -> usize {
+                    Self::rings().1.overflows.load(Ordering::Relaxed)
+                }
+
+                /// Stops the interrupt driven operation and returns the transmitter and receiver
+                /// halves, and the RX and TX buffers
+                ///
+                /// The data still in the ring buffers is discarded.
+                pub fn free(
+                    self,
+                ) -> (
+                    (Tx<$USARTX>, Rx<$USARTX>),
+                    (&'static mut [u8], &'static mut [u8]),
+                ) {
+                    interrupt::free(|_| unsafe {
+                        (*$USARTX::ptr())
+                            .cr1
+                            .modify(|_, w| w.rxneie().clear_bit().txeie().clear_bit())
+                    });
+
+                    let (rx_ring, tx_ring) = Self::rings();
+
+                    ((self.tx, self.rx), (rx_ring.deinit(), tx_ring.deinit()))
+                }
+            }
+
+            impl serial::Read<u8> for BufferedSerial<$USARTX> {
+                // NOTE(Void) reception errors are dropped by the interrupt handler; lost data is
+                // reported by `rx_overflows`
+                type Error = Void;
+
+                fn read(&mut self) -> nb::Result<u8, Void> {
+                    Self::rings().0.dequeue().ok_or(nb::Error::WouldBlock)
+                }
+            }
+
+            impl serial::Write<u8> for BufferedSerial<$USARTX> {
+                type Error = Void;
+
+                fn flush(&mut self) -> nb::Result<(), Void> {
+                    if Self::rings().1.is_empty() {
+                        serial::Write::<u8>::flush(&mut self.tx)
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                fn write(&mut self, byte: u8) -> nb::Result<(), Void> {
+                    if Self::rings().1.enqueue(byte) {
+                        // TXEIE: the interrupt handler sends the data
+                        // NOTE(unsafe) the interrupt handler also modifies CR1; the critical
+                        // section makes the read-modify-write atomic
+                        interrupt::free(|_| unsafe {
+                            (*$USARTX::ptr()).cr1.modify(|_, w| w.txeie().set_bit())
+                        });
+
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+            }
+
+            // NOTE the bytes that don't fit in the TX buffer are dropped and counted by
+            // `tx_overflows`
+            impl fmt::Write for BufferedSerial<$USARTX> {
+                fn write_str(&mut self, s: &str) -> fmt::Result {
+                    let tx_ring = Self::rings().1;
+
+                    let mut dropped = 0;
+                    for byte in s.bytes() {
+                        if serial::Write::<u8>::write(self, byte).is_err() {
+                            dropped += 1;
+                        }
+                    }
+
+                    if dropped != 0 {
+                        tx_ring.overflows.fetch_add(dropped, Ordering::Relaxed);
+                    }
+
+                    Ok(())
+                }
+            }
+        )+
+    }
+}
+
+macro_rules! flow_control {
+    ($(
+        $USARTX:ident: ($usartX:ident, $usartX_flow_control:ident, $usartX_rs485:ident, $APB:ident),
+    )+) => {
+        $(
+            impl<TX, RX, RTS, CTS> Serial<$USARTX, (TX, RX, RTS, CTS)> {
+                /// Configures a USART peripheral to provide serial communication with hardware
+                /// flow control
+                ///
+                /// The receiver deasserts RTS while it can't accept more data and the transmitter
+                /// only sends data while CTS is asserted.
+                ///
+                /// # Panics
+                ///
+                /// If the baud rate can't be achieved or the frame (data and parity bits) is not
+                /// 7, 8 or 9 bits long
+                pub fn $usartX_flow_control<C>(
+                    usart: $USARTX,
+                    pins: (TX, RX, RTS, CTS),
+                    config: C,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    C: Into<Config>,
+                    TX: TxPin<$USARTX>,
+                    RX: RxPin<$USARTX>,
+                    RTS: RtsPin<$USARTX>,
+                    CTS: CtsPin<$USARTX>,
+                {
+                    let (tx, rx, rts, cts) = pins;
+                    let (usart, (tx, rx)) =
+                        Serial::$usartX(usart, (tx, rx), config, clocks, apb).free();
+
+                    // NOTE RTSE and CTSE can only be written while the USART is disabled
+                    usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    usart.cr3.modify(|_, w| w.rtse().set_bit().ctse().set_bit());
+                    usart.cr1.modify(|_, w| w.ue().set_bit());
+
+                    Serial {
+                        usart,
+                        pins: (tx, rx, rts, cts),
+                    }
+                }
+            }
+
+            impl<TX, RX, DE> Serial<$USARTX, (TX, RX, DE)> {
+                /// Configures a USART peripheral to drive an RS-485 transceiver
+                ///
+                /// The driver enable signal is output on the `DE` pin while transmitting.
+                ///
+                /// # Panics
+                ///
+                /// If the baud rate can't be achieved, the frame (data and parity bits) is not 7, 8
+                /// or 9 bits long or the driver enable times are longer than 31 sample times
+                pub fn $usartX_rs485<C>(
+                    usart: $USARTX,
+                    pins: (TX, RX, DE),
+                    config: C,
+                    de: DriverEnable,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    C: Into<Config>,
+                    TX: TxPin<$USARTX>,
+                    RX: RxPin<$USARTX>,
+                    DE: RtsPin<$USARTX>,
+                {
+                    let config = config.into();
+                    let (deat, dedt) = de.times(config.oversampling);
+
+                    let (tx, rx, de_pin) = pins;
+                    let (usart, (tx, rx)) =
+                        Serial::$usartX(usart, (tx, rx), config, clocks, apb).free();
+
+                    // NOTE DEM, DEP, DEAT and DEDT can only be written while the USART is disabled
+                    usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    // DEM: driver enable mode
+                    // DEP: driver enable polarity
+                    usart.cr3.modify(|_, w| {
+                        w.dem()
+                            .set_bit()
+                            .dep()
+                            .bit(de.polarity == DePolarity::ActiveLow)
+                    });
+                    usart.cr1.modify(|_, w| unsafe {
+                        w.deat().bits(deat).dedt().bits(dedt).ue().set_bit()
+                    });
+
+                    Serial {
+                        usart,
+                        pins: (tx, rx, de_pin),
+                    }
+                }
+            }
+        )+
+    }
+}
+
+macro_rules! modes {
+    ($(
+        $USARTX:ident: (
+            $usartX_half_duplex:ident,
+            $usartX_lin:ident,
+            $usartX_irda:ident,
+            $APB:ident,
+            $pclkX:ident
+        ),
+    )+) => {
+        $(
+            impl<TX> Serial<$USARTX, TX> {
+                /// Configures a USART peripheral for single-wire half-duplex communication on the
+                /// `TX` pin
+                ///
+                /// The pin should be configured as open drain with a pull-up. The receiver also
+                /// receives the data sent by the transmitter.
+                ///
+                /// # Panics
+                ///
+                /// If the baud rate can't be achieved or the frame (data and parity bits) is not
+                /// 7, 8 or 9 bits long
+                pub fn $usartX_half_duplex<C>(
+                    usart: $USARTX,
+                    pin: TX,
+                    config: C,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    C: Into<Config>,
+                    TX: TxPin<$USARTX>,
+                {
+                    let mut serial = Self::configure(usart, pin, config.into(), clocks, apb);
+
+                    // HDSEL: half-duplex selection
+                    serial.reconfigure(|usart| usart.cr3.modify(|_, w| w.hdsel().set_bit()));
+
+                    serial
+                }
+            }
+
+            impl<TX, RX> Serial<$USARTX, (TX, RX)> {
+                /// Configures a USART peripheral as a LIN master or slave
+                ///
+                /// The frames are 8N1. Masters start a LIN frame with `send_break`; slaves listen
+                /// for the `LinBreak` event, which detects 11-bit breaks.
+                ///
+                /// # Panics
+                ///
+                /// If the baud rate can't be achieved
+                pub fn $usartX_lin(
+                    usart: $USARTX,
+                    pins: (TX, RX),
+                    baud_rate: Bps,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    TX: TxPin<$USARTX>,
+                    RX: RxPin<$USARTX>,
+                {
+                    let config = Config::default().baud_rate(baud_rate);
+                    let mut serial = Self::configure(usart, pins, config, clocks, apb);
+
+                    // LINEN: LIN mode
+                    // LBDL: 11-bit break detection
+                    serial.reconfigure(|usart| {
+                        usart.cr2.modify(|_, w| w.linen().set_bit().lbdl().set_bit())
+                    });
+
+                    serial
+                }
+
+                /// Configures a USART peripheral to drive an IrDA SIR transceiver
+                ///
+                /// # Panics
+                ///
+                /// If the baud rate can't be achieved, the frame (data and parity bits) is not
+                /// 8 or 9 bits long or the configuration doesn't use 1 stop bit
+                pub fn $usartX_irda<C>(
+                    usart: $USARTX,
+                    pins: (TX, RX),
+                    config: C,
+                    mode: IrdaMode,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    C: Into<Config>,
+                    TX: TxPin<$USARTX>,
+                    RX: RxPin<$USARTX>,
+                {
+                    let config = config.into();
+                    assert!(config.m_bits() != 1 << 28, "IrDA doesn't support 7-bit frames");
+                    assert!(
+                        config.stop_bits == StopBits::Stop1,
+                        "IrDA requires 1 stop bit"
+                    );
+
+                    // NOTE the normal mode requires a prescaler of 1
+                    let psc = match mode {
+                        IrdaMode::Normal => 1,
+                        IrdaMode::LowPower => {
+                            let psc = (clocks.$pclkX().0 + 921_600) / 1_843_200;
+                            assert!(psc >= 1 && psc <= 255, "impossible IrDA low power clock");
+                            psc
+                        }
+                    };
+
+                    let mut serial = Self::configure(usart, pins, config, clocks, apb);
+
+                    // PSC: IrDA prescaler
+                    // IREN: IrDA mode
+                    // IRLP: IrDA low power
+                    serial.reconfigure(|usart| {
+                        usart.gtpr.modify(|_, w| unsafe { w.psc().bits(psc as u8) });
+                        usart.cr3.modify(|_, w| {
+                            w.iren()
+                                .set_bit()
+                                .irlp()
+                                .bit(mode == IrdaMode::LowPower)
+                        });
+                    });
+
+                    serial
+                }
+            }
+        )+
+    }
+}
+
+macro_rules! smartcard {
+    ($($USARTX:ident: ($usartX_smartcard:ident, $APB:ident, $pclkX:ident),)+) => {
+        $(
+            impl<TX, CK> Serial<$USARTX, (TX, CK)> {
+                /// Configures a USART peripheral as an ISO 7816 SmartCard interface
+                ///
+                /// `TX` is the bidirectional data line and should be configured as open drain;
+                /// `CK` outputs the card `clock`. The frames have 8 data bits, even parity and 1.5
+                /// stop bits. The receiver NACKs the characters received with a parity error and
+                /// the transmitter repeats the characters NACKed by the card up to 3 times.
+                ///
+                /// # Panics
+                ///
+                /// If the baud rate or the card clock can't be achieved
+                pub fn $usartX_smartcard<F>(
+                    usart: $USARTX,
+                    pins: (TX, CK),
+                    baud_rate: Bps,
+                    clock: F,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    F: Into<Hertz>,
+                    TX: TxPin<$USARTX>,
+                    CK: CkPin<$USARTX>,
+                {
+                    // CK = fck / (2 * PSC)
+                    let psc = clocks.$pclkX().0 / (2 * clock.into().0);
+                    assert!(psc >= 1 && psc < 32, "impossible card clock");
+
+                    let config = Config::default()
+                        .baud_rate(baud_rate)
+                        .parity(Parity::Even)
+                        .stop_bits(StopBits::Stop1p5);
+                    let mut serial = Self::configure(usart, pins, config, clocks, apb);
+
+                    // PSC: card clock prescaler
+                    // CLKEN: output the card clock
+                    // SCEN: SmartCard mode
+                    // NACK: NACK the parity errors
+                    // SCARCNT: retransmit the NACKed characters up to 3 times
+                    serial.reconfigure(|usart| {
+                        usart.gtpr.modify(|_, w| unsafe { w.psc().bits(psc as u8) });
+                        usart.cr2.modify(|_, w| w.clken().set_bit());
+                        usart.cr3.modify(|_, w| unsafe {
+                            w.scen().set_bit().nack().set_bit().scarcnt().bits(3)
+                        });
+                    });
+
+                    serial
+                }
+            }
+        )+
+    }
+}
+
+macro_rules! synchronous {
+    ($($USARTX:ident: ($usartX:ident, $APB:ident),)+) => {
+        $(
+            impl<CK, TX, RX> SyncSerial<$USARTX, (CK, TX, RX)> {
+                /// Configures a USART peripheral to operate as a full duplex SPI master
+                ///
+                /// `CK` outputs the clock, `TX` is MOSI and `RX` is MISO. The frames are 8 bits
+                /// long and sent MSB first; the USART has no slave select output.
+                ///
+                /// # Panics
+                ///
+                /// If the clock frequency can't be achieved
+                pub fn $usartX<F>(
+                    usart: $USARTX,
+                    pins: (CK, TX, RX),
+                    mode: Mode,
+                    last_bit: LastBitClock,
+                    freq: F,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    F: Into<Hertz>,
+                    CK: CkPin<$USARTX>,
+                    TX: TxPin<$USARTX>,
+                    RX: RxPin<$USARTX>,
+                {
+                    // NOTE the CK frequency is the baud rate; oversampling by 8 allows up to fck/8
+                    let config = Config::default()
+                        .baud_rate(Bps(freq.into().0))
+                        .oversampling(Oversampling::Over8);
+                    let mut serial =
+                        Serial::<$USARTX, _>::configure(usart, pins, config, clocks, apb);
+
+                    // CLKEN: output the clock
+                    // CPOL: polarity
+                    // CPHA: phase
+                    // LBCL: last bit clock pulse
+                    // MSBFIRST: MSB first
+                    serial.reconfigure(|usart| {
+                        usart.cr2.modify(|_, w| {
+                            w.clken()
+                                .set_bit()
+                                .cpol()
+                                .bit(mode.polarity == Polarity::IdleHigh)
+                                .cpha()
+                                .bit(mode.phase == Phase::CaptureOnSecondTransition)
+                                .lbcl()
+                                .bit(last_bit == LastBitClock::Output)
+                                .msbfirst()
+                                .set_bit()
+                        })
+                    });
+
+                    let Serial { usart, pins } = serial;
+                    SyncSerial { usart, pins }
+                }
+
+                /// Releases the USART peripheral and associated pins
+                pub fn free(self) -> ($USARTX, (CK, TX, RX)) {
+                    (self.usart, self.pins)
+                }
+            }
+
+            impl<PINS> FullDuplex<u8> for SyncSerial<$USARTX, PINS> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u8, Error> {
+                    let isr = self.usart.isr.read();
+
+                    // NOTE framing, noise and parity errors don't apply to synchronous mode
+                    Err(if isr.ore().bit_is_set() {
+                        self.usart.icr.write(|w| w.orecf().set_bit());
+                        nb::Error::Other(Error::Overrun)
+                    } else if isr.rxne().bit_is_set() {
+                        // NOTE(read_volatile) read only 1 byte (the svd2rust API only allows
+                        // reading a half-word)
+                        return Ok(unsafe {
+                            ptr::read_volatile(&self.usart.rdr as *const _ as *const u8)
+                        });
+                    } else {
+                        nb::Error::WouldBlock
+                    })
+                }
+
+                fn send(&mut self, byte: u8) -> nb::Result<(), Error> {
+                    if self.usart.isr.read().txe().bit_is_set() {
+                        // NOTE(write_volatile) see note above
+                        unsafe {
+                            ptr::write_volatile(&self.usart.tdr as *const _ as *mut u8, byte)
+                        }
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+            }
+
+            impl<PINS> blocking::spi::transfer::Default<u8> for SyncSerial<$USARTX, PINS> {}
+
+            impl<PINS> blocking::spi::write::Default<u8> for SyncSerial<$USARTX, PINS> {}
+        )+
+    }
+}
+
+macro_rules! wake_up {
+    ($($USARTX:ident: ($usartX_wake_up:ident, $usartXsw:ident, $APB:ident),)+) => {
+        $(
+            impl<TX, RX> Serial<$USARTX, (TX, RX)> {
+                /// Configures a USART peripheral to keep receiving in Stop mode
+                ///
+                /// The USART is clocked by the `stop_mode` kernel clock instead of its APB clock.
+                /// The `WakeUp` event is raised by the `stop_mode` wake-up source once the wake-up
+                /// is enabled; see `enable_wake_up`.
+                ///
+                /// # Panics
+                ///
+                /// If the baud rate can't be achieved with the kernel clock
+                pub fn $usartX_wake_up<C>(
+                    usart: $USARTX,
+                    pins: (TX, RX),
+                    config: C,
+                    stop_mode: StopMode,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    C: Into<Config>,
+                    TX: TxPin<$USARTX>,
+                    RX: RxPin<$USARTX>,
+                {
+                    let config = config.into();
+                    let StopMode { clock, source } = stop_mode;
+                    let brr = config.brr(match clock {
+                        StopModeClock::Lse => rcc::LSE,
+                        StopModeClock::Hsi => rcc::HSI,
+                    });
+
+                    let mut serial = Self::configure(usart, pins, config, clocks, apb);
+
+                    // NOTE the kernel clock must only be switched while the USART is disabled
+                    serial.reconfigure(|usart| {
+                        rcc::modify_cfgr3(|_, w| unsafe { w.$usartXsw().bits(clock as u8) });
+
+                        usart.brr.write(|w| unsafe { w.bits(brr) });
+
+                        // WUS: wake-up event source
+                        usart.cr3.modify(|_, w| unsafe { w.wus().bits(source as u8) });
+                    });
+
+                    serial
+                }
+            }
+
+            impl<PINS> Serial<$USARTX, PINS> {
+                /// Lets the USART wake the MCU up from Stop mode
+                ///
+                /// The MCU is woken up by the `WakeUp` event, which must be listened for; the
+                /// USART EXTI line is unmasked on reset. The USART must have been created with the
+                /// `usartX_wake_up` constructor.
+                pub fn enable_wake_up(&mut self) {
+                    // UESM: USART enabled in Stop mode
+                    self.usart.cr1.modify(|_, w| w.uesm().set_bit());
+                }
+
+                /// Stops the USART from waking the MCU up from Stop mode
+                pub fn disable_wake_up(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.uesm().clear_bit());
+                }
+            }
+        )+
+    }
+}
+
+macro_rules! auto_baud_rate {
+    ($($USARTX:ident: ($usartXsw:ident, $pclkX:ident),)+) => {
+        $(
+            impl<PINS> Serial<$USARTX, PINS> {
+                // Frequency of the kernel clock selected in the RCC
+                fn kernel_clock(clocks: Clocks) -> u32 {
+                    // NOTE(unsafe) atomic read with no side effects
+                    match unsafe { (*RCC::ptr()).cfgr3.read().$usartXsw().bits() } {
+                        0b00 => clocks.$pclkX().0,
+                        0b01 => clocks.sysclk().0,
+                        0b10 => rcc::LSE,
+                        _ => rcc::HSI,
+                    }
+                }
+
+                /// Starts an automatic baud rate detection on the next received character
+                ///
+                /// Use `auto_baud_rate` to wait for the result. The character used for the
+                /// detection is received as usual.
+                pub fn start_auto_baud_rate(&mut self, mode: AutoBaudRateMode) {
+                    // NOTE ABREN and ABRMOD can only be written while the USART is disabled; this
+                    // also clears the result of the previous detection
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart.cr2.modify(|_, w| unsafe {
+                        w.abrmod().bits(mode as u8).abren().set_bit()
+                    });
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+
+                /// Returns the detected baud rate once the detection started by
+                /// `start_auto_baud_rate` completes
+                ///
+                /// The USART is then configured to communicate at that baud rate.
+                pub fn auto_baud_rate(&mut self, clocks: Clocks) -> nb::Result<Bps, Error> {
+                    let isr = self.usart.isr.read();
+
+                    if isr.abre().bit_is_set() {
+                        self.usart.cr2.modify(|_, w| w.abren().clear_bit());
+                        Err(nb::Error::Other(Error::AutoBaudRate))
+                    } else if isr.abrf().bit_is_set() {
+                        self.usart.cr2.modify(|_, w| w.abren().clear_bit());
+
+                        let brr = self.usart.brr.read().bits();
+                        let fck = Self::kernel_clock(clocks);
+
+                        Ok(Bps(if self.usart.cr1.read().over8().bit_is_set() {
+                            // NOTE BRR[2:0] = USARTDIV[3:0] >> 1
+                            2 * fck / ((brr & !0xf) | ((brr & 0x7) << 1))
+                        } else {
+                            fck / brr
+                        }))
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+            }
+        )+
+    }
+}
+
+macro_rules! dma {
+    ($(
+        $USARTX:ident: $dmaX:ident::{tx: $TXCH:ident, rx: $RXCH:ident},
+    )+) => {
+        $(
+            impl Tx<$USARTX> {
+                /// Sends the whole `buffer` using the `channel` dedicated to this transmitter
+                ///
+                /// The transfer is done once the last byte has been shifted out of the transmitter
+                ///
+                /// # Panics
+                ///
+                /// If `buffer` is empty
+                pub fn write_all<B>(
+                    self,
+                    buffer: &'static B,
+                    mut channel: $dmaX::$TXCH,
+                ) -> Transfer<W, &'static B, TxDma<Tx<$USARTX>, $dmaX::$TXCH>>
+                where
+                    B: AsRef<[u8]>,
+                {
+                    {
+                        let slice = buffer.as_ref();
+                        // NOTE the DMA never completes an empty transfer
+                        assert!(!slice.is_empty(), "empty buffer");
+
+                        // NOTE(unsafe) the transmitter owns the TDR register
+                        let tdr = unsafe { &(*$USARTX::ptr()).tdr as *const _ as u32 };
+                        channel.set_peripheral_address(tdr, false);
+                        channel.set_memory_address(slice.as_ptr() as u32, true);
+                        channel.set_transfer_length(slice.len());
+                    }
+
+                    // MEM2MEM: peripheral transfer
+                    // PL: medium priority
+                    // MSIZE, PSIZE: 8-bit items
+                    // CIRC: one shot
+                    // DIR: read from memory
+                    channel.ccr().modify(|_, w| unsafe {
+                        w.mem2mem()
+                            .clear_bit()
+                            .pl()
+                            .bits(0b01)
+                            .msize()
+                            .bits(0b00)
+                            .psize()
+                            .bits(0b00)
+                            .circ()
+                            .clear_bit()
+                            .dir()
+                            .set_bit()
+                    });
+
+                    // the buffer must be written before the DMA reads it
+                    atomic::compiler_fence(Ordering::Release);
+
+                    let mut payload = TxDma {
+                        payload: self,
+                        channel,
+                    };
+                    payload.start();
+
+                    Transfer::w(buffer, payload)
+                }
+            }
+
+            impl Rx<$USARTX> {
+                /// Fills the whole `buffer` using the `channel` dedicated to this receiver
+                ///
+                /// # Panics
+                ///
+                /// If `buffer` is empty
+                pub fn read_exact<B>(
+                    self,
+                    buffer: &'static mut B,
+                    mut channel: $dmaX::$RXCH,
+                ) -> Transfer<R, &'static mut B, RxDma<Rx<$USARTX>, $dmaX::$RXCH>>
+                where
+                    B: AsMut<[u8]>,
+                {
+                    {
+                        let slice = buffer.as_mut();
+                        // NOTE the DMA never completes an empty transfer
+                        assert!(!slice.is_empty(), "empty buffer");
+
+                        let (address, len) = (slice.as_mut_ptr() as u32, slice.len());
+                        Self::configure(&mut channel, address, len, false);
+                    }
+
+                    let mut payload = RxDma {
+                        payload: self,
+                        channel,
+                    };
+                    payload.start();
+
+                    Transfer::r(buffer, payload)
+                }
+
+                /// Continuously receives into the two halves of `buffer`, in turns, using the
+                /// `channel` dedicated to this receiver
+                ///
+                /// # Panics
+                ///
+                /// If the two halves are empty or not contiguous in memory
+                pub fn circ_read<B>(
+                    self,
+                    buffer: &'static mut [B; 2],
+                    mut channel: $dmaX::$RXCH,
+                ) -> CircBuffer<B, RxDma<Rx<$USARTX>, $dmaX::$RXCH>>
+                where
+                    B: AsMut<[u8]>,
+                {
+                    {
+                        let (first, second) = buffer.split_at_mut(1);
+                        let (first, second) = (first[0].as_mut(), second[0].as_mut());
+                        assert!(!first.is_empty(), "empty buffer halves");
+                        assert!(
+                            first.len() == second.len()
+                                && first.as_ptr() as usize + first.len()
+                                    == second.as_ptr() as usize,
+                            "buffer halves are not contiguous"
+                        );
+
+                        Self::configure(
+                            &mut channel,
+                            first.as_mut_ptr() as u32,
+                            2 * first.len(),
+                            true,
+                        );
+                    }
+
+                    let mut payload = RxDma {
+                        payload: self,
+                        channel,
+                    };
+                    payload.start();
+
+                    CircBuffer::new(buffer, payload)
+                }
+
+                fn configure(channel: &mut $dmaX::$RXCH, address: u32, len: usize, circ: bool) {
+                    // NOTE(unsafe) the receiver owns the RDR register
+                    let rdr = unsafe { &(*$USARTX::ptr()).rdr as *const _ as u32 };
+                    channel.set_peripheral_address(rdr, false);
+                    channel.set_memory_address(address, true);
+                    channel.set_transfer_length(len);
+
+                    // MEM2MEM: peripheral transfer
+                    // PL: medium priority
+                    // MSIZE, PSIZE: 8-bit items
+                    // DIR: read from peripheral
+                    channel.ccr().modify(|_, w| unsafe {
+                        w.mem2mem()
+                            .clear_bit()
+                            .pl()
+                            .bits(0b01)
+                            .msize()
+                            .bits(0b00)
+                            .psize()
+                            .bits(0b00)
+                            .circ()
+                            .bit(circ)
+                            .dir()
+                            .clear_bit()
+                    });
+                }
+            }
+
+            impl TransferPayload for TxDma<Tx<$USARTX>, $dmaX::$TXCH> {
+                fn start(&mut self) {
+                    // NOTE(unsafe) write-to-clear register with no side effects on other flags
+                    // TC stays set after the previous transmission; clear it so that `is_idle`
+                    // only reports the end of this one
+                    unsafe { (*$USARTX::ptr()).icr.write(|w| w.tccf().set_bit()) }
+
+                    self.channel.start();
+
+                    // NOTE(unsafe) CR3 is shared with the receiver; the critical section makes
+                    // the read-modify-write atomic
+                    interrupt::free(|_| unsafe {
+                        (*$USARTX::ptr()).cr3.modify(|_, w| w.dmat().set_bit())
+                    });
+                }
+
+                fn stop(&mut self) {
+                    // NOTE(unsafe) see `start`
+                    interrupt::free(|_| unsafe {
+                        (*$USARTX::ptr()).cr3.modify(|_, w| w.dmat().clear_bit())
+                    });
+
+                    self.channel.stop();
+                }
+
+                fn error(&self) -> Option<dma::Error> {
+                    None
+                }
+
+                fn is_idle(&self) -> bool {
+                    // NOTE(unsafe) atomic read with no side effects
+                    // the DMA is done once it has written the last byte into TDR; the
+                    // transmission is done once that byte has left the shift register
+                    unsafe { (*$USARTX::ptr()).isr.read().tc().bit_is_set() }
+                }
+            }
+
+            impl TransferPayload for RxDma<Rx<$USARTX>, $dmaX::$RXCH> {
+                fn start(&mut self) {
+                    // NOTE(unsafe) write-to-clear register with no side effects on other flags
+                    // discard the overrun of a previous reception, if any
+                    unsafe { (*$USARTX::ptr()).icr.write(|w| w.orecf().set_bit()) }
+
+                    self.channel.start();
+
+                    // NOTE(unsafe) CR3 is shared with the transmitter; the critical section
+                    // makes the read-modify-write atomic
+                    interrupt::free(|_| unsafe {
+                        (*$USARTX::ptr()).cr3.modify(|_, w| w.dmar().set_bit())
+                    });
+                }
+
+                fn stop(&mut self) {
+                    // NOTE(unsafe) see `start`
+                    interrupt::free(|_| unsafe {
+                        (*$USARTX::ptr()).cr3.modify(|_, w| w.dmar().clear_bit())
+                    });
+
+                    self.channel.stop();
+                }
+
+                fn error(&self) -> Option<dma::Error> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    if unsafe { (*$USARTX::ptr()).isr.read().ore().bit_is_set() } {
+                        Some(dma::Error::Peripheral)
+                    } else {
+                        None
+                    }
+                }
+
+                fn is_idle(&self) -> bool {
+                    true
+                }
+            }
+        )+
+    }
+}
+
+hal! {
+    USART1: (usart1, APB2, usart1en, usart1rst, usart1sw, pclk2),
+    USART2: (usart2, APB1, usart2en, usart2rst, usart2sw, pclk1),
+    USART3: (usart3, APB1, usart3en, usart3rst, usart3sw, pclk1),
+    UART4: (uart4, APB1, 19, uart4rst, uart4sw, pclk1),
+    UART5: (uart5, APB1, 20, uart5rst, uart5sw, pclk1),
+}
+
+flow_control! {
+    USART1: (usart1, usart1_flow_control, usart1_rs485, APB2),
+    USART2: (usart2, usart2_flow_control, usart2_rs485, APB1),
+    USART3: (usart3, usart3_flow_control, usart3_rs485, APB1),
+}
+
+modes! {
+    USART1: (usart1_half_duplex, usart1_lin, usart1_irda, APB2, pclk2),
+    USART2: (usart2_half_duplex, usart2_lin, usart2_irda, APB1, pclk1),
+    USART3: (usart3_half_duplex, usart3_lin, usart3_irda, APB1, pclk1),
+    UART4: (uart4_half_duplex, uart4_lin, uart4_irda, APB1, pclk1),
+    UART5: (uart5_half_duplex, uart5_lin, uart5_irda, APB1, pclk1),
+}
+
+smartcard! {
+    USART1: (usart1_smartcard, APB2, pclk2),
+    USART2: (usart2_smartcard, APB1, pclk1),
+    USART3: (usart3_smartcard, APB1, pclk1),
+}
+
+synchronous! {
+    USART1: (usart1, APB2),
+    USART2: (usart2, APB1),
+    USART3: (usart3, APB1),
+}
+
+wake_up! {
+    USART1: (usart1_wake_up, usart1sw, APB2),
+    USART2: (usart2_wake_up, usart2sw, APB1),
+    USART3: (usart3_wake_up, usart3sw, APB1),
+    UART4: (uart4_wake_up, uart4sw, APB1),
+    UART5: (uart5_wake_up, uart5sw, APB1),
+}
+
+auto_baud_rate! {
+    USART1: (usart1sw, pclk2),
+    USART2: (usart2sw, pclk1),
+    USART3: (usart3sw, pclk1),
+}
+
+dma! {
+    USART1: dma1::{tx: C4, rx: C5},
+    USART2: dma1::{tx: C7, rx: C6},
+    USART3: dma1::{tx: C2, rx: C3},
+    UART4: dma2::{tx: C5, rx: C3},
 }
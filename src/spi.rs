@@ -1,5 +1,6 @@
 //! Serial Peripheral Interface (SPI) bus
 
+use core::marker::PhantomData;
 use core::ptr;
 
 use hal::spi::{FullDuplex, Mode, Phase, Polarity};
@@ -36,6 +37,29 @@ pub unsafe trait MisoPin<SPI> {}
 /// MOSI pin -- DO NOT IMPLEMENT THIS TRAIT
 pub unsafe trait MosiPin<SPI> {}
 
+/// Data frame word -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait Word {
+    #[doc(hidden)]
+    const MIN_SIZE: u8;
+    #[doc(hidden)]
+    const MAX_SIZE: u8;
+    // RXNE threshold; set if RXNE must be raised when the RX FIFO holds 8 bits, as opposed to 16
+    #[doc(hidden)]
+    const FRXTH: bool;
+}
+
+unsafe impl Word for u8 {
+    const MIN_SIZE: u8 = 4;
+    const MAX_SIZE: u8 = 8;
+    const FRXTH: bool = true;
+}
+
+unsafe impl Word for u16 {
+    const MIN_SIZE: u8 = 9;
+    const MAX_SIZE: u8 = 16;
+    const FRXTH: bool = false;
+}
+
 unsafe impl SckPin<SPI1> for PA5<AF5> {}
 // unsafe impl SckPin<SPI1> for PB3<AF5> {}
 
@@ -61,9 +85,13 @@ unsafe impl MosiPin<SPI3> for PB5<AF6> {}
 unsafe impl MosiPin<SPI3> for PC12<AF6> {}
 
 /// SPI peripheral operating in full duplex master mode
-pub struct Spi<SPI, PINS> {
+///
+/// `WORD` is `u8` for data frames of 4 to 8 bits and `u16` for data frames of 9 to 16 bits; see
+/// `Spi::data_size`
+pub struct Spi<SPI, PINS, WORD = u8> {
     spi: SPI,
     pins: PINS,
+    _word: PhantomData<WORD>,
 }
 
 macro_rules! hal {
@@ -144,15 +172,71 @@ macro_rules! hal {
                             .clear_bit()
                     });
 
-                    Spi { spi, pins }
+                    Spi {
+                        spi,
+                        pins,
+                        _word: PhantomData,
+                    }
                 }
+            }
 
+            impl<SCK, MISO, MOSI, WORD> Spi<$SPIX, (SCK, MISO, MOSI), WORD> {
                 /// Releases the SPI peripheral and associated pins
                 pub fn free(self) -> ($SPIX, (SCK, MISO, MOSI)) {
                     (self.spi, self.pins)
                 }
             }
 
+            impl<PINS, WORD> Spi<$SPIX, PINS, WORD> {
+                /// Changes the size of the data frames to `bits`
+                ///
+                /// The frames are exchanged as `u8` words if `bits` is 4 to 8 and as `u16` words
+                /// if `bits` is 9 to 16. This waits until the frames in flight have been sent and
+                /// discards the received frames that haven't been read.
+                ///
+                /// # Panics
+                ///
+                /// If `bits` is out of the range of `NEWWORD`
+                pub fn data_size<NEWWORD>(self, bits: u8) -> Spi<$SPIX, PINS, NEWWORD>
+                where
+                    NEWWORD: Word,
+                {
+                    assert!(
+                        bits >= NEWWORD::MIN_SIZE && bits <= NEWWORD::MAX_SIZE,
+                        "unsupported data size"
+                    );
+
+                    // NOTE the SPI is disabled while its configuration changes, following the
+                    // procedure of RM0316: wait until the TX FIFO is empty and the last frame has
+                    // been sent, then disable the SPI and drain the RX FIFO
+                    while self.spi.sr.read().ftlvl().bits() != 0 {}
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    while self.spi.sr.read().frlvl().bits() != 0 {
+                        // NOTE(read_volatile) the frames left in the FIFO would be misread after
+                        // the change of data size
+                        unsafe {
+                            ptr::read_volatile(&self.spi.dr as *const _ as *const u8);
+                        }
+                    }
+
+                    // FRXTH: RXNE event is generated if the FIFO level is greater than or equal to
+                    //        one word
+                    // DS: data size
+                    self.spi.cr2.modify(|_, w| unsafe {
+                        w.frxth().bit(NEWWORD::FRXTH).ds().bits(bits - 1)
+                    });
+
+                    self.spi.cr1.modify(|_, w| w.spe().set_bit());
+
+                    Spi {
+                        spi: self.spi,
+                        pins: self.pins,
+                        _word: PhantomData,
+                    }
+                }
+            }
+
             impl<PINS> FullDuplex<u8> for Spi<$SPIX, PINS> {
                 type Error = Error;
 
@@ -195,9 +279,55 @@ macro_rules! hal {
                 }
             }
 
+            impl<PINS> FullDuplex<u16> for Spi<$SPIX, PINS, u16> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u16, Error> {
+                    let sr = self.spi.sr.read();
+
+                    Err(if sr.ovr().bit_is_set() {
+                        nb::Error::Other(Error::Overrun)
+                    } else if sr.modf().bit_is_set() {
+                        nb::Error::Other(Error::ModeFault)
+                    } else if sr.crcerr().bit_is_set() {
+                        nb::Error::Other(Error::Crc)
+                    } else if sr.rxne().bit_is_set() {
+                        // NOTE(read_volatile) read a half-word; the svd2rust API performs a
+                        // word-sized access
+                        return Ok(unsafe {
+                            ptr::read_volatile(&self.spi.dr as *const _ as *const u16)
+                        });
+                    } else {
+                        nb::Error::WouldBlock
+                    })
+                }
+
+                fn send(&mut self, word: u16) -> nb::Result<(), Error> {
+                    let sr = self.spi.sr.read();
+
+                    Err(if sr.ovr().bit_is_set() {
+                        nb::Error::Other(Error::Overrun)
+                    } else if sr.modf().bit_is_set() {
+                        nb::Error::Other(Error::ModeFault)
+                    } else if sr.crcerr().bit_is_set() {
+                        nb::Error::Other(Error::Crc)
+                    } else if sr.txe().bit_is_set() {
+                        // NOTE(write_volatile) see note above
+                        unsafe { ptr::write_volatile(&self.spi.dr as *const _ as *mut u16, word) }
+                        return Ok(());
+                    } else {
+                        nb::Error::WouldBlock
+                    })
+                }
+            }
+
             impl<PINS> ::hal::blocking::spi::transfer::Default<u8> for Spi<$SPIX, PINS> {}
 
             impl<PINS> ::hal::blocking::spi::write::Default<u8> for Spi<$SPIX, PINS> {}
+
+            impl<PINS> ::hal::blocking::spi::transfer::Default<u16> for Spi<$SPIX, PINS, u16> {}
+
+            impl<PINS> ::hal::blocking::spi::write::Default<u16> for Spi<$SPIX, PINS, u16> {}
         )+
     }
 }
@@ -20,6 +20,14 @@ pub struct KiloHertz(pub u32);
 #[derive(Clone, Copy)]
 pub struct MegaHertz(pub u32);
 
+/// Milliseconds
+#[derive(Clone, Copy)]
+pub struct MilliSeconds(pub u32);
+
+/// Microseconds
+#[derive(Clone, Copy)]
+pub struct MicroSeconds(pub u32);
+
 /// Extension trait that adds convenience methods to the `u32` type
 pub trait U32Ext {
     /// Wrap in `Bps`
@@ -33,6 +41,12 @@ pub trait U32Ext {
 
     /// Wrap in `MegaHertz`
     fn mhz(self) -> MegaHertz;
+
+    /// Wrap in `MilliSeconds`
+    fn ms(self) -> MilliSeconds;
+
+    /// Wrap in `MicroSeconds`
+    fn us(self) -> MicroSeconds;
 }
 
 impl U32Ext for u32 {
@@ -51,6 +65,14 @@ impl U32Ext for u32 {
     fn mhz(self) -> MegaHertz {
         MegaHertz(self)
     }
+
+    fn ms(self) -> MilliSeconds {
+        MilliSeconds(self)
+    }
+
+    fn us(self) -> MicroSeconds {
+        MicroSeconds(self)
+    }
 }
 
 impl Into<Hertz> for KiloHertz {
@@ -71,6 +93,18 @@ impl Into<KiloHertz> for MegaHertz {
     }
 }
 
+/// Panics if the duration doesn't fit in `u32` microseconds, i.e. if it's longer than about 71
+/// minutes
+impl Into<MicroSeconds> for MilliSeconds {
+    fn into(self) -> MicroSeconds {
+        MicroSeconds(
+            self.0
+                .checked_mul(1_000)
+                .expect("duration too long to be expressed in microseconds"),
+        )
+    }
+}
+
 /// A monotonic nondecreasing timer
 #[derive(Clone, Copy)]
 pub struct MonoTimer {
@@ -0,0 +1,100 @@
+//! Timeouts for blocking operations
+//!
+//! A `Deadline` is checked by the blocking drivers while they busy wait; they give up with a
+//! timeout error once it expires. A `CountDown` timer provides a `Deadline`:
+//!
+//! ``` ignore
+//! let mut deadline = timeout::CountDownDeadline::start(&mut timer, 10.ms());
+//! let byte = timeout::block(&mut deadline, || rx.read())?;
+//! ```
+
+use hal::timer::CountDown;
+use nb;
+
+/// Error of an operation that can time out
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The deadline expired before the operation completed
+    TimedOut,
+    /// The operation failed
+    Other(E),
+}
+
+/// A deadline for blocking operations
+pub trait Deadline {
+    /// Returns `true` if the deadline has expired
+    ///
+    /// Once expired, a deadline stays expired
+    fn expired(&mut self) -> bool;
+}
+
+/// A deadline that never expires
+pub struct Never;
+
+impl Deadline for Never {
+    fn expired(&mut self) -> bool {
+        false
+    }
+}
+
+/// A deadline that expires when the count down of a timer ends
+///
+/// The expiration is latched: the deadline stays expired even if the timer is periodic and starts
+/// a new count down.
+pub struct CountDownDeadline<'a, C>
+where
+    C: 'a,
+{
+    timer: &'a mut C,
+    expired: bool,
+}
+
+impl<'a, C> CountDownDeadline<'a, C>
+where
+    C: CountDown,
+{
+    /// Starts the count down of `timer` and returns a deadline that expires when it ends
+    pub fn start<T>(timer: &'a mut C, count: T) -> Self
+    where
+        T: Into<C::Time>,
+    {
+        timer.start(count);
+
+        CountDownDeadline {
+            timer,
+            expired: false,
+        }
+    }
+}
+
+impl<'a, C> Deadline for CountDownDeadline<'a, C>
+where
+    C: CountDown,
+{
+    fn expired(&mut self) -> bool {
+        if !self.expired {
+            self.expired = self.timer.wait().is_ok();
+        }
+
+        self.expired
+    }
+}
+
+/// Retries the non-blocking operation `f` until it completes or the `deadline` expires
+pub fn block<D, T, E, F>(deadline: &mut D, mut f: F) -> Result<T, Error<E>>
+where
+    D: Deadline,
+    F: FnMut() -> nb::Result<T, E>,
+{
+    loop {
+        match f() {
+            Ok(t) => return Ok(t),
+            Err(nb::Error::Other(e)) => return Err(Error::Other(e)),
+            Err(nb::Error::WouldBlock) => {
+                if deadline.expired() {
+                    return Err(Error::TimedOut);
+                }
+            }
+        }
+    }
+}
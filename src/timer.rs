@@ -1,70 +1,513 @@
 //! Timers
 
-use cast::{u16, u32};
+use core::cmp;
+
+use cast::{u32, u64};
+use hal::blocking::delay::{DelayMs, DelayUs};
 use hal::timer::{CountDown, Periodic};
 use nb;
-use stm32f30x::{TIM2, TIM3, TIM4, TIM6, TIM7};
+use stm32f30x::{TIM15, TIM16, TIM17, TIM2, TIM3, TIM4, TIM6, TIM7};
 use void::Void;
 
-use rcc::{APB1, Clocks};
-use time::Hertz;
+use gpio::gpioa::{PA0, PA1, PA10, PA11, PA12, PA2, PA3, PA4, PA5, PA6, PA7, PA9};
+use gpio::gpiob::{PB0, PB1, PB10, PB11, PB14, PB15, PB5, PB6, PB7, PB8, PB9};
+use gpio::gpioc::{PC6, PC7, PC8, PC9};
+use gpio::gpiod::{PD12, PD13, PD14, PD15, PD2, PD3, PD4, PD6, PD7};
+use gpio::gpioe::{PE0, PE1, PE2, PE3, PE4, PE5};
+use gpio::gpiof::{PF10, PF6, PF9};
+use gpio::{AF1, AF10, AF2, AF3, AF4, AF9};
+use rcc::{APB1, APB2, Clocks};
+use time::{Hertz, KiloHertz, MegaHertz, MicroSeconds, MilliSeconds};
 
 /// Hardware timers
 pub struct Timer<TIM> {
     clocks: Clocks,
     tim: TIM,
-    timeout: Hertz,
+    timeout: Period,
 }
 
 /// Interrupt events
 pub enum Event {
     /// Timer timed out / count down ended
     TimeOut,
+    /// Capture or compare match on channel 1
+    CaptureCompare1,
+    /// Capture or compare match on channel 2
+    CaptureCompare2,
+    /// Capture or compare match on channel 3
+    CaptureCompare3,
+    /// Capture or compare match on channel 4
+    CaptureCompare4,
+    /// Commutation (COM) event
+    Commutation,
+    /// Trigger input event
+    Trigger,
+    /// Break input event
+    Break,
+}
+
+impl Event {
+    // position of the event in the DIER and SR registers
+    fn mask(&self) -> u32 {
+        1 << match *self {
+            Event::TimeOut => 0,
+            Event::CaptureCompare1 => 1,
+            Event::CaptureCompare2 => 2,
+            Event::CaptureCompare3 => 3,
+            Event::CaptureCompare4 => 4,
+            Event::Commutation => 5,
+            Event::Trigger => 6,
+            Event::Break => 7,
+        }
+    }
+}
+
+/// Timer error
+#[derive(Debug)]
+pub enum Error {
+    /// The period is longer than what the timer can count
+    PeriodTooLong,
+    /// The period is shorter than two cycles of the timer clock
+    PeriodTooShort,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Period of a timer
+///
+/// Can be created from a frequency (`Hertz`, `KiloHertz`, `MegaHertz`) or from a duration
+/// (`MilliSeconds`, `MicroSeconds`)
+#[derive(Clone, Copy)]
+pub struct Period {
+    // the period is `num / den` seconds
+    num: u32,
+    den: u32,
+}
+
+impl Period {
+    /// Computes the prescaler and auto-reload values that best approximate this period
+    ///
+    /// Returns `(PSC, ARR, error)` where `error` is the difference between the achieved period
+    /// and the requested one, in cycles of `clock`
+    pub(crate) fn psc_arr(&self, clock: Hertz, arr_max: u32) -> Result<(u16, u32, i32), Error> {
+        if self.den == 0 {
+            // 0 Hz
+            return Err(Error::PeriodTooLong);
+        }
+
+        let (num, den) = (u64(self.num), u64(self.den));
+
+        // period in cycles of the timer clock, rounded to the nearest integer
+        let ticks = (u64(clock.0) * num + den / 2) / den;
+
+        // NOTE the smallest prescaler gives the finest auto-reload granularity, which bounds the
+        // rounding error to half a prescaler cycle
+        let cnt_max = u64(arr_max) + 1;
+        let psc = cmp::max((ticks + cnt_max - 1) / cnt_max, 1);
+        if psc > 1 << 16 {
+            return Err(Error::PeriodTooLong);
+        }
+
+        let arr = (ticks + psc / 2) / psc;
+        if arr < 2 {
+            // ARR = 0 would stop the counter
+            return Err(Error::PeriodTooShort);
+        }
+
+        // NOTE(as) `psc` <= 1 << 16, `arr` <= `cnt_max` and the error is at most `psc / 2`
+        let error = (psc * arr) as i64 - ticks as i64;
+        Ok(((psc - 1) as u16, (arr - 1) as u32, error as i32))
+    }
+}
+
+impl Into<Period> for Hertz {
+    fn into(self) -> Period {
+        Period {
+            num: 1,
+            den: self.0,
+        }
+    }
+}
+
+impl Into<Period> for KiloHertz {
+    fn into(self) -> Period {
+        let hz: Hertz = self.into();
+        hz.into()
+    }
+}
+
+impl Into<Period> for MegaHertz {
+    fn into(self) -> Period {
+        let hz: Hertz = self.into();
+        hz.into()
+    }
+}
+
+impl Into<Period> for MilliSeconds {
+    fn into(self) -> Period {
+        Period {
+            num: self.0,
+            den: 1_000,
+        }
+    }
+}
+
+impl Into<Period> for MicroSeconds {
+    fn into(self) -> Period {
+        Period {
+            num: self.0,
+            den: 1_000_000,
+        }
+    }
+}
+
+/// Signals that can be sent to the trigger output (TRGO) of a master timer
+pub mod trgo {
+    /// The UG bit
+    pub struct Reset;
+    /// The counter enable signal
+    pub struct Enable;
+    /// The update event
+    pub struct Update;
+    /// A pulse on every capture or compare match of channel 1
+    pub struct ComparePulse;
+    /// The OC1REF signal
+    pub struct Oc1Ref;
+    /// The OC2REF signal
+    pub struct Oc2Ref;
+    /// The OC3REF signal
+    pub struct Oc3Ref;
+    /// The OC4REF signal
+    pub struct Oc4Ref;
+
+    // values of the MMS field
+    impl Reset {
+        pub(crate) const MMS: u8 = 0b000;
+    }
+
+    impl Enable {
+        pub(crate) const MMS: u8 = 0b001;
+    }
+
+    impl Update {
+        pub(crate) const MMS: u8 = 0b010;
+    }
+
+    impl ComparePulse {
+        pub(crate) const MMS: u8 = 0b011;
+    }
+
+    impl Oc1Ref {
+        pub(crate) const MMS: u8 = 0b100;
+    }
+
+    impl Oc2Ref {
+        pub(crate) const MMS: u8 = 0b101;
+    }
+
+    impl Oc3Ref {
+        pub(crate) const MMS: u8 = 0b110;
+    }
+
+    impl Oc4Ref {
+        pub(crate) const MMS: u8 = 0b111;
+    }
+}
+
+/// How a slave timer reacts to its trigger input
+pub enum SlaveMode {
+    /// A rising edge of the trigger reinitializes the counter
+    Reset,
+    /// The counter runs while the trigger is high
+    Gated,
+    /// A rising edge of the trigger starts the counter
+    Trigger,
+    /// The counter is clocked by the rising edges of the trigger
+    ExternalClock,
+}
+
+/// Timer channel -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait Channel {
+    /// Zero based index of the channel
+    const INDEX: u8;
+}
+
+/// Channel 1 (type state)
+pub struct C1;
+/// Channel 2 (type state)
+pub struct C2;
+/// Channel 3 (type state)
+pub struct C3;
+/// Channel 4 (type state)
+pub struct C4;
+
+unsafe impl Channel for C1 {
+    const INDEX: u8 = 0;
+}
+
+unsafe impl Channel for C2 {
+    const INDEX: u8 = 1;
+}
+
+unsafe impl Channel for C3 {
+    const INDEX: u8 = 2;
+}
+
+unsafe impl Channel for C4 {
+    const INDEX: u8 = 3;
+}
+
+/// Channel whose input can trigger the slave mode controller -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait TriggerChannel: Channel {}
+
+unsafe impl TriggerChannel for C1 {}
+unsafe impl TriggerChannel for C2 {}
+
+// FIXME these should be "closed" traits
+/// Timer channel pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait CPin<TIM, CHANNEL> {}
+
+unsafe impl CPin<TIM2, C1> for PA0<AF1> {}
+unsafe impl CPin<TIM2, C1> for PA5<AF1> {}
+// unsafe impl CPin<TIM2, C1> for PA15<AF1> {}
+unsafe impl CPin<TIM2, C1> for PD3<AF2> {}
+
+unsafe impl CPin<TIM2, C2> for PA1<AF1> {}
+// unsafe impl CPin<TIM2, C2> for PB3<AF1> {}
+unsafe impl CPin<TIM2, C2> for PD4<AF2> {}
+
+unsafe impl CPin<TIM2, C3> for PA2<AF1> {}
+unsafe impl CPin<TIM2, C3> for PA9<AF10> {}
+unsafe impl CPin<TIM2, C3> for PB10<AF1> {}
+unsafe impl CPin<TIM2, C3> for PD7<AF2> {}
+
+unsafe impl CPin<TIM2, C4> for PA3<AF1> {}
+unsafe impl CPin<TIM2, C4> for PA10<AF10> {}
+unsafe impl CPin<TIM2, C4> for PB11<AF1> {}
+unsafe impl CPin<TIM2, C4> for PD6<AF2> {}
+
+unsafe impl CPin<TIM3, C1> for PA6<AF2> {}
+// unsafe impl CPin<TIM3, C1> for PB4<AF2> {}
+unsafe impl CPin<TIM3, C1> for PC6<AF2> {}
+unsafe impl CPin<TIM3, C1> for PE2<AF2> {}
+
+unsafe impl CPin<TIM3, C2> for PA4<AF2> {}
+unsafe impl CPin<TIM3, C2> for PA7<AF2> {}
+unsafe impl CPin<TIM3, C2> for PB5<AF2> {}
+unsafe impl CPin<TIM3, C2> for PC7<AF2> {}
+unsafe impl CPin<TIM3, C2> for PE3<AF2> {}
+
+unsafe impl CPin<TIM3, C3> for PB0<AF2> {}
+unsafe impl CPin<TIM3, C3> for PC8<AF2> {}
+unsafe impl CPin<TIM3, C3> for PE4<AF2> {}
+
+unsafe impl CPin<TIM3, C4> for PB1<AF2> {}
+unsafe impl CPin<TIM3, C4> for PB7<AF10> {}
+unsafe impl CPin<TIM3, C4> for PC9<AF2> {}
+unsafe impl CPin<TIM3, C4> for PE5<AF2> {}
+
+unsafe impl CPin<TIM4, C1> for PA11<AF10> {}
+unsafe impl CPin<TIM4, C1> for PB6<AF2> {}
+unsafe impl CPin<TIM4, C1> for PD12<AF2> {}
+
+unsafe impl CPin<TIM4, C2> for PA12<AF10> {}
+unsafe impl CPin<TIM4, C2> for PB7<AF2> {}
+unsafe impl CPin<TIM4, C2> for PD13<AF2> {}
+
+// unsafe impl CPin<TIM4, C3> for PA13<AF10> {}
+unsafe impl CPin<TIM4, C3> for PB8<AF2> {}
+unsafe impl CPin<TIM4, C3> for PD14<AF2> {}
+
+unsafe impl CPin<TIM4, C4> for PB9<AF2> {}
+unsafe impl CPin<TIM4, C4> for PD15<AF2> {}
+unsafe impl CPin<TIM4, C4> for PF6<AF2> {}
+
+unsafe impl CPin<TIM15, C1> for PA2<AF9> {}
+unsafe impl CPin<TIM15, C1> for PB14<AF1> {}
+unsafe impl CPin<TIM15, C1> for PF9<AF3> {}
+
+unsafe impl CPin<TIM15, C2> for PA3<AF9> {}
+unsafe impl CPin<TIM15, C2> for PB15<AF1> {}
+unsafe impl CPin<TIM15, C2> for PF10<AF3> {}
+
+unsafe impl CPin<TIM16, C1> for PA6<AF1> {}
+unsafe impl CPin<TIM16, C1> for PA12<AF1> {}
+// unsafe impl CPin<TIM16, C1> for PB4<AF1> {}
+unsafe impl CPin<TIM16, C1> for PB8<AF1> {}
+unsafe impl CPin<TIM16, C1> for PE0<AF4> {}
+
+unsafe impl CPin<TIM17, C1> for PA7<AF1> {}
+unsafe impl CPin<TIM17, C1> for PB5<AF10> {}
+unsafe impl CPin<TIM17, C1> for PB9<AF1> {}
+unsafe impl CPin<TIM17, C1> for PE1<AF4> {}
+
+/// Signal that the `TIM` timer can send to its trigger output -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait TriggerOutput<TIM> {
+    /// Value of the MMS field that selects this signal
+    const MMS: u8;
+}
+
+/// External trigger (ETR) pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait EtrPin<TIM> {}
+
+unsafe impl EtrPin<TIM2> for PA0<AF1> {}
+unsafe impl EtrPin<TIM2> for PA5<AF1> {}
+// unsafe impl EtrPin<TIM2> for PA15<AF1> {}
+unsafe impl EtrPin<TIM2> for PD3<AF2> {}
+
+unsafe impl EtrPin<TIM3> for PD2<AF2> {}
+
+unsafe impl EtrPin<TIM4> for PE0<AF2> {}
+
+/// Internal trigger connection from the `MASTER` timer to this timer -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait InternalTrigger<MASTER> {
+    /// Value of the TS field that selects `MASTER` as the trigger input
+    const TS: u8;
+}
+
+// NOTE ITR connections to TIM1 and TIM8 have been omitted
+unsafe impl InternalTrigger<TIM3> for TIM2 {
+    const TS: u8 = 0b010;
+}
+
+unsafe impl InternalTrigger<TIM4> for TIM2 {
+    const TS: u8 = 0b011;
+}
+
+unsafe impl InternalTrigger<TIM2> for TIM3 {
+    const TS: u8 = 0b001;
+}
+
+unsafe impl InternalTrigger<TIM15> for TIM3 {
+    const TS: u8 = 0b010;
+}
+
+unsafe impl InternalTrigger<TIM4> for TIM3 {
+    const TS: u8 = 0b011;
+}
+
+unsafe impl InternalTrigger<TIM2> for TIM4 {
+    const TS: u8 = 0b001;
+}
+
+unsafe impl InternalTrigger<TIM3> for TIM4 {
+    const TS: u8 = 0b010;
+}
+
+unsafe impl InternalTrigger<TIM2> for TIM15 {
+    const TS: u8 = 0b000;
+}
+
+unsafe impl InternalTrigger<TIM3> for TIM15 {
+    const TS: u8 = 0b001;
+}
+
+// NOTE the trigger is the OC1 output of TIM16 / TIM17, not their TRGO
+unsafe impl InternalTrigger<TIM16> for TIM15 {
+    const TS: u8 = 0b010;
+}
+
+unsafe impl InternalTrigger<TIM17> for TIM15 {
+    const TS: u8 = 0b011;
 }
 
 macro_rules! hal {
-    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident),)+) => {
+    ($($TIM:ident: (
+        $tim:ident,
+        $APB:ident,
+        $timXen:ident,
+        $timXrst:ident,
+        $pclkX:ident,
+        $ppreX:ident,
+        $width:ident,
+        $events:expr
+    ),)+) => {
         $(
             impl Periodic for Timer<$TIM> {}
 
             impl CountDown for Timer<$TIM> {
-                type Time = Hertz;
+                type Time = Period;
 
-                // NOTE(allow) `w.psc().bits()` is safe for TIM{6,7} but not for TIM{2,3,4} due to
-                // some SVD omission
-                #[allow(unused_unsafe)]
+                /// Starts the timer with the given period
+                ///
+                /// # Panics
+                ///
+                /// If the timer can't achieve the period; see `try_start` for a fallible version
                 fn start<T>(&mut self, timeout: T)
                 where
-                    T: Into<Hertz>,
+                    T: Into<Period>,
                 {
+                    self.try_start(timeout).expect("impossible timer period");
+                }
+
+                fn wait(&mut self) -> nb::Result<(), Void> {
+                    if self.tim.sr.read().uif().bit_is_clear() {
+                        Err(nb::Error::WouldBlock)
+                    } else {
+                        self.clear(Event::TimeOut);
+                        Ok(())
+                    }
+                }
+            }
+
+            // NOTE a delay reprograms the timer and leaves it paused; it cancels any count down in
+            // progress
+            impl DelayUs<u32> for Timer<$TIM> {
+                fn delay_us(&mut self, mut us: u32) {
+                    // NOTE long delays are split so that they fit in the 16-bit timers
+                    while us > 1_000_000 {
+                        self.delay_us(1_000_000_u32);
+                        us -= 1_000_000;
+                    }
+
+                    if us != 0 {
+                        if self.try_start(MicroSeconds(us)).is_err() {
+                            // NOTE delays shorter than two timer ticks can't be timed; they are
+                            // rounded up to two ticks
+                            let shortest = Hertz(self.clock().0 / 2);
+                            self.start(shortest);
+                        }
+
+                        while self.wait().is_err() {}
+                    }
+
                     // pause
                     self.tim.cr1.modify(|_, w| w.cen().clear_bit());
-                    // restart counter
-                    self.tim.cnt.reset();
+                }
+            }
 
-                    self.timeout = timeout.into();
+            impl DelayUs<u16> for Timer<$TIM> {
+                fn delay_us(&mut self, us: u16) {
+                    self.delay_us(u32(us))
+                }
+            }
 
-                    let frequency = self.timeout.0;
-                    let ticks = self.clocks.pclk1().0 * if self.clocks.ppre1() == 1 { 1 } else { 2 }
-                        / frequency;
+            impl DelayUs<u8> for Timer<$TIM> {
+                fn delay_us(&mut self, us: u8) {
+                    self.delay_us(u32(us))
+                }
+            }
 
-                    let psc = u16((ticks - 1) / (1 << 16)).unwrap();
-                    self.tim.psc.write(|w| unsafe { w.psc().bits(psc) });
+            impl DelayMs<u32> for Timer<$TIM> {
+                fn delay_ms(&mut self, mut ms: u32) {
+                    while ms > 1_000 {
+                        self.delay_us(1_000_000_u32);
+                        ms -= 1_000;
+                    }
 
-                    let arr = u16(ticks / u32(psc + 1)).unwrap();
-                    self.tim.arr.write(|w| unsafe { w.bits(u32(arr)) });
+                    self.delay_us(ms * 1_000);
+                }
+            }
 
-                    // start counter
-                    self.tim.cr1.modify(|_, w| w.cen().set_bit());
+            impl DelayMs<u16> for Timer<$TIM> {
+                fn delay_ms(&mut self, ms: u16) {
+                    self.delay_ms(u32(ms));
                 }
+            }
 
-                fn wait(&mut self) -> nb::Result<(), Void> {
-                    if self.tim.sr.read().uif().bit_is_clear() {
-                        Err(nb::Error::WouldBlock)
-                    } else {
-                        self.tim.sr.modify(|_, w| w.uif().clear_bit());
-                        Ok(())
-                    }
+            impl DelayMs<u8> for Timer<$TIM> {
+                fn delay_ms(&mut self, ms: u8) {
+                    self.delay_ms(u32(ms));
                 }
             }
 
@@ -73,43 +516,116 @@ macro_rules! hal {
                 // even if the `$TIM` are non overlapping (compare to the `free` function below
                 // which just works)
                 /// Configures a TIM peripheral as a periodic count down timer
-                pub fn $tim<T>(tim: $TIM, timeout: T, clocks: Clocks, apb1: &mut APB1) -> Self
+                pub fn $tim<T>(tim: $TIM, timeout: T, clocks: Clocks, apb: &mut $APB) -> Self
                 where
-                    T: Into<Hertz>,
+                    T: Into<Period>,
                 {
                     // enable and reset peripheral to a clean slate state
-                    apb1.enr().modify(|_, w| w.$timXen().set_bit());
-                    apb1.rstr().modify(|_, w| w.$timXrst().set_bit());
-                    apb1.rstr().modify(|_, w| w.$timXrst().clear_bit());
+                    apb.enr().modify(|_, w| w.$timXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().clear_bit());
 
+                    let timeout = timeout.into();
                     let mut timer = Timer {
                         clocks,
                         tim,
-                        timeout: Hertz(0),
+                        timeout,
                     };
                     timer.start(timeout);
 
                     timer
                 }
 
+                /// Starts the timer with the given period, or returns an error if the timer can't
+                /// achieve it
+                ///
+                /// On success, returns the rounding error: the difference between the achieved
+                /// period and the requested one, in cycles of the timer clock (see `clock`). On
+                /// error, the timer configuration is left untouched.
+                // NOTE(allow) `w.psc().bits()` is safe for TIM{6,7,15,16,17} but not for TIM{2,3,4}
+                // due to some SVD omission
+                #[allow(unused_unsafe)]
+                pub fn try_start<T>(&mut self, timeout: T) -> Result<i32, Error>
+                where
+                    T: Into<Period>,
+                {
+                    let timeout = timeout.into();
+                    let (psc, arr, error) =
+                        timeout.psc_arr(self.clock(), u32($width::max_value()))?;
+
+                    // pause
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    // restart counter
+                    self.tim.cnt.reset();
+
+                    self.tim.psc.write(|w| unsafe { w.psc().bits(psc) });
+                    self.tim.arr.write(|w| unsafe { w.bits(arr) });
+
+                    // URS: only counter overflows raise the update flag
+                    self.tim.cr1.modify(|_, w| w.urs().set_bit());
+                    // UG: load the new prescaler value right away
+                    self.tim.egr.write(|w| w.ug().set_bit());
+                    // discard the overflows of the previous count down, if any
+                    self.clear(Event::TimeOut);
+
+                    self.timeout = timeout;
+
+                    // start counter
+                    self.tim.cr1.modify(|_, w| w.cen().set_bit());
+
+                    Ok(error)
+                }
+
+                /// Returns the frequency of the clock that drives the timer, before prescaling
+                pub fn clock(&self) -> Hertz {
+                    // NOTE the timer clock is twice the APB clock when the APB prescaler is not 1
+                    Hertz(self.clocks.$pclkX().0 * if self.clocks.$ppreX() == 1 { 1 } else { 2 })
+                }
+
                 /// Starts listening for an `event`
+                ///
+                /// # Panics
+                ///
+                /// If the timer doesn't support `event`
                 pub fn listen(&mut self, event: Event) {
-                    match event {
-                        Event::TimeOut => {
-                            // Enable update event interrupt
-                            self.tim.dier.write(|w| w.uie().set_bit());
-                        }
-                    }
+                    let mask = Self::supported(event);
+                    self.tim.dier.modify(|r, w| unsafe { w.bits(r.bits() | mask) });
                 }
 
                 /// Stops listening for an `event`
+                ///
+                /// # Panics
+                ///
+                /// If the timer doesn't support `event`
                 pub fn unlisten(&mut self, event: Event) {
-                    match event {
-                        Event::TimeOut => {
-                            // Enable update event interrupt
-                            self.tim.dier.write(|w| w.uie().clear_bit());
-                        }
-                    }
+                    let mask = Self::supported(event);
+                    self.tim.dier.modify(|r, w| unsafe { w.bits(r.bits() & !mask) });
+                }
+
+                /// Returns `true` if `event` has occurred and hasn't been cleared yet
+                ///
+                /// # Panics
+                ///
+                /// If the timer doesn't support `event`
+                pub fn is_pending(&self, event: Event) -> bool {
+                    self.tim.sr.read().bits() & Self::supported(event) != 0
+                }
+
+                /// Clears the pending flag of `event`
+                ///
+                /// # Panics
+                ///
+                /// If the timer doesn't support `event`
+                pub fn clear(&mut self, event: Event) {
+                    let mask = Self::supported(event);
+                    // NOTE(unsafe) the SR flags are cleared by writing zero; ones are ignored
+                    self.tim.sr.write(|w| unsafe { w.bits(!mask) });
+                }
+
+                fn supported(event: Event) -> u32 {
+                    let mask = event.mask();
+                    assert!(mask & $events != 0, "event not supported by this timer");
+                    mask
                 }
 
                 /// Releases the TIM peripheral
@@ -124,9 +640,139 @@ macro_rules! hal {
 }
 
 hal! {
-    TIM2: (tim2, tim2en, tim2rst),
-    TIM3: (tim3, tim3en, tim3rst),
-    TIM4: (tim4, tim4en, tim4rst),
-    TIM6: (tim6, tim6en, tim6rst),
-    TIM7: (tim7, tim7en, tim7rst),
+    TIM2: (tim2, APB1, tim2en, tim2rst, pclk1, ppre1, u32, 0b0101_1111),
+    TIM3: (tim3, APB1, tim3en, tim3rst, pclk1, ppre1, u16, 0b0101_1111),
+    TIM4: (tim4, APB1, tim4en, tim4rst, pclk1, ppre1, u16, 0b0101_1111),
+    TIM6: (tim6, APB1, tim6en, tim6rst, pclk1, ppre1, u16, 0b0000_0001),
+    TIM7: (tim7, APB1, tim7en, tim7rst, pclk1, ppre1, u16, 0b0000_0001),
+    TIM15: (tim15, APB2, tim15en, tim15rst, pclk2, ppre2, u16, 0b1110_0111),
+    TIM16: (tim16, APB2, tim16en, tim16rst, pclk2, ppre2, u16, 0b1110_0011),
+    TIM17: (tim17, APB2, tim17en, tim17rst, pclk2, ppre2, u16, 0b1110_0011),
+}
+
+macro_rules! master {
+    ($($TIM:ident: [$($TRGO:ident),+],)+) => {
+        $(
+            $(
+                unsafe impl TriggerOutput<$TIM> for trgo::$TRGO {
+                    const MMS: u8 = trgo::$TRGO::MMS;
+                }
+            )+
+
+            impl Timer<$TIM> {
+                /// Selects the signal sent to the trigger output (TRGO)
+                ///
+                /// TRGO can drive the trigger input of other timers (see `set_slave_mode`) and
+                /// start ADC or DAC conversions.
+                // NOTE(allow) `w.mms().bits()` is safe for some timers but not for others
+                #[allow(unused_unsafe)]
+                pub fn set_trigger_output<TRGO>(&mut self, _trgo: TRGO)
+                where
+                    TRGO: TriggerOutput<$TIM>,
+                {
+                    self.tim.cr2.modify(|_, w| unsafe { w.mms().bits(TRGO::MMS) });
+                }
+            }
+        )+
+    }
+}
+
+macro_rules! slave {
+    ($($TIM:ident,)+) => {
+        $(
+            impl Timer<$TIM> {
+                /// Slaves this timer to the trigger output of the `MASTER` timer
+                ///
+                /// With `SlaveMode::Trigger` the counter is stopped and starts counting once the
+                /// trigger arrives. Note that `start` enables the counter right away so it must be
+                /// called before this method.
+                pub fn set_slave_mode<MASTER>(&mut self, mode: SlaveMode)
+                where
+                    $TIM: InternalTrigger<MASTER>,
+                {
+                    // TS: ITRx
+                    self.set_trigger_input(<$TIM as InternalTrigger<MASTER>>::TS, mode);
+                }
+
+                /// Disables the slave mode; the counter is once again driven by the timer clock
+                pub fn disable_slave_mode(&mut self) {
+                    // SMS: slave mode disabled
+                    self.tim.smcr.modify(|r, w| unsafe { w.bits(r.bits() & !(0b111 | (1 << 16))) });
+                }
+
+                fn set_trigger_input(&mut self, ts: u8, mode: SlaveMode) {
+                    let sms = match mode {
+                        SlaveMode::Reset => 0b100,
+                        SlaveMode::Gated => 0b101,
+                        SlaveMode::Trigger => 0b110,
+                        SlaveMode::ExternalClock => 0b111,
+                    };
+
+                    if let SlaveMode::Trigger = mode {
+                        // wait for the trigger
+                        self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    }
+
+                    // TS: trigger selection
+                    // SMS: slave mode
+                    let ts = u32(ts);
+                    self.tim.smcr.modify(|r, w| unsafe {
+                        w.bits((r.bits() & !((0b111 << 4) | 0b111 | (1 << 16))) | (ts << 4) | sms)
+                    });
+                }
+            }
+        )+
+    }
+}
+
+macro_rules! external_trigger {
+    ($($TIM:ident,)+) => {
+        $(
+            impl Timer<$TIM> {
+                /// Slaves this timer to the signal on its external trigger (ETR) `pin`
+                ///
+                /// The signal is active high (rising edges) and sampled without prescaler nor
+                /// filter. With `SlaveMode::Gated` the counter runs while the signal is high. Note
+                /// that `start` enables the counter right away so it must be called before this
+                /// method.
+                pub fn set_external_slave_mode<PIN>(&mut self, mode: SlaveMode, _pin: &PIN)
+                where
+                    PIN: EtrPin<$TIM>,
+                {
+                    // ETF: no filter
+                    // ETPS: no prescaler
+                    // ECE: external clock mode 2 disabled
+                    // ETP: non inverted
+                    self.tim
+                        .smcr
+                        .modify(|r, w| unsafe { w.bits(r.bits() & !(0xff << 8)) });
+
+                    // TS: ETRF
+                    self.set_trigger_input(0b111, mode);
+                }
+            }
+        )+
+    }
+}
+
+master! {
+    TIM2: [Reset, Enable, Update, ComparePulse, Oc1Ref, Oc2Ref, Oc3Ref, Oc4Ref],
+    TIM3: [Reset, Enable, Update, ComparePulse, Oc1Ref, Oc2Ref, Oc3Ref, Oc4Ref],
+    TIM4: [Reset, Enable, Update, ComparePulse, Oc1Ref, Oc2Ref, Oc3Ref, Oc4Ref],
+    TIM6: [Reset, Enable, Update],
+    TIM7: [Reset, Enable, Update],
+    TIM15: [Reset, Enable, Update, ComparePulse, Oc1Ref, Oc2Ref],
+}
+
+slave! {
+    TIM2,
+    TIM3,
+    TIM4,
+    TIM15,
+}
+
+external_trigger! {
+    TIM2,
+    TIM3,
+    TIM4,
 }